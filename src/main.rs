@@ -1,42 +1,202 @@
 use std::env;
+use std::ops::Range;
 use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 use std::time::Instant;
 
+use modules::fakeserver;
 use modules::helpers;
 use modules::jason;
-use modules::mining::MinoeringResult;
+use modules::jason::AccountSplit;
+use modules::jason::ConfigFile;
+use modules::ledger::Ledger;
+use modules::mining::MinoerEvent;
+use modules::mining::INPUT_SIZE;
 use modules::network;
+use modules::network::failover::Endpoints;
+use modules::network::ratelimit::Limited;
+use modules::network::ratelimit::Limiter;
+use modules::network::workclient::ProxyJob;
+use modules::network::workclient::WorkClient;
+use modules::network::workproxy;
+use modules::outbox::Find;
 use modules::mining::miner;
-use modules::mining::miner::DISPATCH_SIZE;
+use modules::mining::miner::Minoer;
+use modules::mining::tune;
+use modules::session::Session;
 
 mod modules;
 
-/** max dispatches for each thread
+/** max pre-recorded dispatches for each thread, device memory can make it less
  */
-const MAX_DISPATCHES: usize = 8;
+const MAX_DISPATCHES: usize = 32;
+
+/** longest the main loop waits on the gpus before checking on the submitter
+ */
+const REPORT_POLL: Duration = Duration::from_millis(50);
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     println!("Hello, world!");
 
-    let (results_sender, results_reciever) = mpsc::channel::<MinoeringResult>();
+    // `gpu-miner fakeserver [address] [fail rate]` pretends to be gabserver for offline testing, no config needed
+    if env::args().nth(1).as_deref() == Some("fakeserver") {
+        fakeserver::run(&env::args().skip(2).collect::<Vec<_>>());
+        return;
+    }
+
+    // `gpu-miner earnings` sums up ledger.jsonl, also works while another copy is mining
+    if env::args().nth(1).as_deref() == Some("earnings") {
+        Ledger::open().print_summary();
+        return;
+    }
 
-    let mut miner = miner::Minoer::new(MAX_DISPATCHES, results_sender);
-    let client = reqwest::blocking::Client::new();
-    let config = jason::read_config();
+    let mut config = jason::read_config();
 
     if !config.lobotomize {
         loop { println!("tampering detected!!!!!!") }
     }
 
-    println!("minoering..");
-    let mut hash = network::get_hash(&client, &config);
-    // let hash = String::from("8deda67f452dc5de673a01fad1580ca4429bc166a4e3dc5d3911535616327e32");
+    // `gpu-miner autotune` benchmarks kernel setups and caches the fastest for this gpu
+    if env::args().nth(1).as_deref() == Some("autotune") {
+        tune::autotune(Duration::from_millis(config.batch_target_ms));
+        return;
+    }
+
+    // `gpu-miner proxy` talks to the server for the miners on the lan and doesnt mine itself
+    if env::args().nth(1).as_deref() == Some("proxy") {
+        workproxy::run(&config);
+        return;
+    }
+
+    // the work proxy decides the prefix so the miners behind it never overlap
+    let work = config.work_proxy.as_deref().map(WorkClient::connect);
+    if let Some(work) = &work {
+        config.rig_id = Some(work.subscribed.rig_id);
+        config.rig_digits = work.subscribed.rig_digits;
+        config.nonce_encoding = work.subscribed.nonce_encoding;
+    }
+
+    let config = Arc::new(config);
+    let sessions: Vec<Session> = match &work {
+        Some(work) => {
+            let jobs = &work.subscribed.jobs;
+            let tagged = jobs.iter().any(|j| j.username != jobs[0].username);
+            let limiters = Limiter::per_account(jobs.iter().map(|j| j.username.as_str()), config.submit_limit);
+            jobs.iter()
+                .map(|job| {
+                    let pool = Arc::new(ProxyJob::new(work.clone(), &job.job_id));
+                    let pool = Arc::new(Limited::new(pool, limiters[&job.username].clone()));
+                    Session::with_pool(pool, None, &config, &job.username, job.weight, job.thread_id, tagged)
+                })
+                .collect()
+        },
+        None => {
+            let endpoints = Endpoints::new(network::build_client(&config), &config);
+            let tagged = config.accounts.len() > 1;
+            let limiters = Limiter::per_account(config.accounts.iter().map(|a| a.username.as_str()), config.submit_limit);
+            let ledger = Arc::new(Mutex::new(Ledger::open()));
+            config.thread_ids.iter()
+                .flat_map(|thread_id| config.accounts.iter().map(move |account| (account, *thread_id)))
+                .map(|(account, thread_id)| {
+                    let limiter = limiters[&account.username].clone();
+                    Session::new(endpoints.clone(), limiter, ledger.clone(), &config, account, thread_id, tagged)
+                })
+                .collect()
+        },
+    };
+
+    // every (threadid, account) pair mines its own chain of hashes, either side by side on a share
+    // of the gpu threads or taking turns on all of them
+    let mut lanes: Vec<(Vec<Session>, u32)> = Vec::new();
+    for session in sessions {
+        match config.account_split {
+            AccountSplit::Devices => {
+                let weight = session.weight;
+                lanes.push((vec![session], weight));
+            },
+            AccountSplit::Rotate => match lanes.iter_mut().find(|(s, _)| s[0].thread_id == session.thread_id) {
+                Some((sessions, _)) => sessions.push(session),
+                None => lanes.push((vec![session], 1)),
+            },
+        }
+    }
+    if lanes.is_empty() {
+        panic!("nothing to mine, the work proxy has no jobs");
+    }
+
+    let (results_senders, results_recievers): (Vec<_>, Vec<_>) = lanes.iter()
+        .map(|_| mpsc::channel::<MinoerEvent>())
+        .unzip();
+    let weights: Vec<u32> = lanes.iter().map(|(_, weight)| *weight).collect();
+    let miners = miner::Minoer::split(MAX_DISPATCHES, Duration::from_millis(config.batch_target_ms), results_senders, &weights);
+
+    let rig_id = config.rig_id.unwrap_or_else(helpers::hostname_hash);
+    let rig_prefix = config.nonce_encoding.rig_prefix(rig_id, config.rig_digits);
+    println!("rig {}, nonces start at {}", rig_id, config.nonce_encoding.format(config.nonce_encoding.start() + rig_prefix));
+
+    let lanes: Vec<_> = lanes.into_iter()
+        .zip(miners)
+        .zip(results_recievers)
+        .map(|(((sessions, _), miner), results_reciever)| {
+            let config = config.clone();
+            thread::spawn(move || run_lane(&config, rig_id, miner, results_reciever, sessions))
+        })
+        .collect();
+
+    for lane in lanes {
+        lane.join().unwrap();
+    }
+}
+
+/** mines for `sessions` forever, taking turns if theres more than one
+ */
+fn run_lane(config: &ConfigFile, rig_id: u64, mut miner: Minoer, results_reciever: Receiver<MinoerEvent>, mut sessions: Vec<Session>) {
+    let encoding = config.nonce_encoding;
+    let rig_prefix = encoding.rig_prefix(rig_id, config.rig_digits);
+    let search_key = config.search_key.unwrap_or(rig_id);
+    // our whole block is usually way more than the threads can hold, it gets mined a window at a time
+    let dispatches = encoding.dispatch_limit(config.rig_digits);
+    let window = miner.plan(config.search_order, search_key, dispatches).total();
+
+    let started = Instant::now();
+    let weights: Vec<u32> = sessions.iter().map(|s| s.weight).collect();
+    let period = Duration::from_secs(config.rotate_period_s);
+
+    let names: Vec<String> = sessions.iter().map(|s| s.name()).collect();
+    println!("minoering {} on {} threads..", names.join(", "), miner.threads());
+
     loop {
-        // println!("Hash: {}", hash);
+        let (active, slot_end) = match sessions.len() {
+            1 => (0, None),
+            _ => {
+                let (active, left) = rotation(&weights, period, started.elapsed());
+                println!("{} gets the gpu for {}s", sessions[active].name(), left.as_secs());
+                (active, Some(Instant::now() + left))
+            },
+        };
+
+        let hash = sessions[active].hash();
+        let reseeds = sessions[active].scan_log.reseeds(&hash);
+        let block = match rig_block(reseeds, window, dispatches) {
+            Some(b) => b,
+            None => {
+                // the blocks either side are other rigs', only a new hash gives us more
+                println!("ran out of nonces for {} in our whole block, waiting for a new hash", hash);
+                sessions[active].wait_for_new_hash(&hash);
+                continue;
+            },
+        };
+        // let hash = String::from("8deda67f452dc5de673a01fad1580ca4429bc166a4e3dc5d3911535616327e32");
         let prehash = helpers::sha1_prehash(&hash);
-        let mut data: [u32; 10] = [0; 10];
-        let nonce: u64 = 10000000000000000000;
+        let mut data: [u32; INPUT_SIZE] = [0; INPUT_SIZE];
+        let nonce: u64 = encoding.start() + rig_prefix + block.start * encoding.stride();
+        let permutation = miner.plan(config.search_order, search_key, block.end - block.start);
 
         data[0] = prehash[0];
         data[1] = prehash[1];
@@ -46,38 +206,187 @@ fn main() {
     
         let instant: Instant = Instant::now();
 
-        miner.mine(data, nonce);
+        let scanned = sessions[active].scan_log.begin(&hash, encoding, nonce, permutation);
+        miner.mine(data, nonce, encoding, permutation, &scanned);
 
-        for result in results_reciever.recv() {
-            let mut string = String::new();
-            let real_nonce = result.nonce;
-            let minoers_mined = result.minoers_mined;
-    
-            for x in 0..4 {
-                let mut data = result.hashes[x].to_ne_bytes();
-                data.reverse();
-                string.push_str(&hex::encode(data));
+        loop {
+            // the server only moves on after a find, so sitting and waiting could take forever
+            if miner.idle() {
+                println!("done with dispatches {}..{} of our block for {}, moving on", block.start, block.end, hash);
+                sessions[active].scan_log.reseed(&hash);
+                break;
             }
 
-            let diff = Instant::now().duration_since(instant);
-            let hashes = minoers_mined as u64 * DISPATCH_SIZE as u64 * 64;
-            println!("Took {}s, looked through {} hashes, with ~{}h/s", diff.as_secs(), hashes, (hashes as f64 / (diff.as_millis() as f64 / 1000f64)) as u64);
-            
-            let res = network::send_to_server(&client, &config, real_nonce);
-
-            match res {
-                Some(r) => {
-                    hash = r.newhash;
-                    println!("Nonce got: {}, Hash: {}, Reward: {}", real_nonce, string, r.reward);
+            if slot_end.is_some_and(|end| Instant::now() >= end) {
+                break;
+            }
+
+            // an account thats sitting out can still hear back about a find it sent earlier.
+            // breaking out stops the workers mid job and the new hash gets mined from the top
+            let mut switched = false;
+            for (i, session) in sessions.iter_mut().enumerate() {
+                if session.poll() && i == active {
+                    switched = true;
+                }
+            }
+            if switched {
+                break;
+            }
+
+            let event = match results_reciever.recv_timeout(REPORT_POLL) {
+                Ok(e) => e,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => panic!("all the minoer threads died"),
+            };
+
+            // left over from a job we already moved on from
+            if event.generation() != miner.generation() {
+                continue;
+            }
+
+            let result = match event {
+                MinoerEvent::Found(result) => result,
+                MinoerEvent::Scanned(_, range) => {
+                    sessions[active].scan_log.record(&hash, range);
+                    sessions[active].retry_outbox();
+                    continue;
                 },
-                None => {
-                    println!("bad nonce: {}, hash: {}, source hash {}", real_nonce, string, hash);
-                    hash = network::get_hash(&client, &config);
+                MinoerEvent::Exhausted(_, thread) => {
+                    miner.refill(thread);
+                    continue;
                 },
+            };
+
+            let real_nonce = result.nonce;
+
+            if helpers::sha1_compress(prehash, &result.encoding.message_block(real_nonce))[..] != result.hashes[..] {
+                println!("gpu got nonce {} wrong, not submitting it", result.encoding.format(real_nonce));
+                continue;
+            }
+            let minoers_mined = result.minoers_mined;
+
+            let diff = Instant::now().duration_since(instant);
+            let hashes = minoers_mined as u64 * miner.invocations() as u64;
+            println!("{}: Took {}s, looked through {} hashes, with ~{}h/s", sessions[active].name(), diff.as_secs(), hashes, (hashes as f64 / (diff.as_millis() as f64 / 1000f64)) as u64);
+
+            // the gpus keep going on the current job until the submitter hears back
+            sessions[active].found(Find::new(&hash, real_nonce, result.encoding, &result.hashes), result.found_at);
+        }
+
+        if sessions[active].hash() != hash {
+            sessions[active].scan_log.finish(&hash);
+        }
+
+        miner.stop_mining();
+        println!();
+    }
+}
+
+/**
+ * dispatches of our own block to mine after running out `reseeds` times, a `window` at a time from the bottom.
+ * None once the whole block is done, whats above and below it belongs to other rigs
+ */
+fn rig_block(reseeds: u64, window: u64, dispatches: u64) -> Option<Range<u64>> {
+    let start = reseeds.checked_mul(window.max(1))?;
+    if start >= dispatches {
+        return None;
+    }
+
+    return Some(start..(start + window.max(1)).min(dispatches));
+}
+
+/** whose turn it is `elapsed` into the rotation and how long that turn has left
+ */
+fn rotation(weights: &[u32], period: Duration, elapsed: Duration) -> (usize, Duration) {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    let period_ms = (period.as_millis() as u64).max(total);
+    let at = elapsed.as_millis() as u64 % period_ms;
+
+    let mut end = 0;
+    for (i, weight) in weights.iter().enumerate() {
+        end = if i == weights.len() - 1 { period_ms } else { end + period_ms * *weight as u64 / total };
+        if at < end {
+            return (i, Duration::from_millis(end - at));
+        }
+    }
+
+    return (0, Duration::ZERO);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modules::nonce::NonceEncoding;
+
+    #[test]
+    fn rig_block_walks_our_own_block_and_stops() {
+        for (window, dispatches) in [(10u64, 100u64), (10, 95), (100, 100), (100, 30), (1, 3)] {
+            let mut covered = 0;
+            let mut reseeds = 0;
+            while let Some(block) = rig_block(reseeds, window, dispatches) {
+                assert_eq!(block.start, covered, "{} {}", window, dispatches);
+                assert!(block.end > block.start && block.end - block.start <= window);
+                covered = block.end;
+                reseeds += 1;
+            }
+            assert_eq!(covered, dispatches);
+            assert_eq!(reseeds, dispatches.div_ceil(window));
+            assert_eq!(rig_block(reseeds + 1, window, dispatches), None);
+            assert_eq!(rig_block(u64::MAX, window, dispatches), None);
+        }
+    }
+
+    #[test]
+    fn running_out_never_reaches_the_next_rig() {
+        for encoding in [NonceEncoding::Decimal, NonceEncoding::Hex, NonceEncoding::Base62, NonceEncoding::Binary] {
+            let rig_digits = 2;
+            let dispatches = encoding.dispatch_limit(rig_digits);
+            let window = dispatches / 3 + 1;
+
+            for rig_id in [0u64, 5, encoding.rigs(rig_digits) - 1] {
+                let bottom = encoding.start() + encoding.rig_prefix(rig_id, rig_digits);
+                let next_rig = bottom as u128 + dispatches as u128 * encoding.stride() as u128;
+
+                let mut reseeds = 0;
+                while let Some(block) = rig_block(reseeds, window, dispatches) {
+                    let first = bottom + block.start * encoding.stride();
+                    let last = first as u128 + (block.end - block.start) as u128 * encoding.stride() as u128 - 1;
+                    assert!(first >= bottom && last < next_rig, "{:?} rig {} went past its block", encoding, rig_id);
+                    reseeds += 1;
+                }
+                assert_eq!(reseeds, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_shares_the_period_by_weight() {
+        let period = Duration::from_secs(30);
+        let ms = Duration::from_millis;
+
+        assert_eq!(rotation(&[1, 2], period, ms(0)), (0, ms(10000)));
+        assert_eq!(rotation(&[1, 2], period, ms(9999)), (0, ms(1)));
+        assert_eq!(rotation(&[1, 2], period, ms(10000)), (1, ms(20000)));
+        assert_eq!(rotation(&[1, 2], period, ms(29999)), (1, ms(1)));
+        assert_eq!(rotation(&[1, 2], period, ms(30000 * 7 + 5)), (0, ms(9995)));
+
+        // 10s doesnt split in 3, the last one gets whats left over
+        let period = Duration::from_secs(10);
+        assert_eq!(rotation(&[1, 1, 1], period, ms(3332)), (0, ms(1)));
+        assert_eq!(rotation(&[1, 1, 1], period, ms(3333)), (1, ms(3333)));
+        assert_eq!(rotation(&[1, 1, 1], period, ms(6666)), (2, ms(3334)));
+    }
+
+    #[test]
+    fn rotation_gives_everyone_a_turn_with_a_tiny_period() {
+        for weights in [vec![1u32, 1000], vec![3, 1, 1]] {
+            let mut turns = vec![0; weights.len()];
+            for at in 0..2000 {
+                let (active, left) = rotation(&weights, Duration::ZERO, Duration::from_millis(at));
+                assert!(left > Duration::ZERO);
+                turns[active] += 1;
             }
-            miner.stop_mining();
-            println!();
-            // break 'outer;
+            assert!(turns.iter().all(|t| *t > 0), "{:?} {:?}", weights, turns);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,326 @@
+// pretend gabserver for testing the whole miner offline, speaks just enough http for reqwest
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::modules::helpers;
+
+const REWARD: u32 = 10;
+
+/**
+ * leading zero bits a hash needs. the miner only reports hashes whose whole first word is zero
+ * (DIFFICULTY_MASK) and the real server doesnt say what it wants, so this has to match that
+ */
+const DIFFICULTY_BITS: u32 = 32;
+
+/** how long a "hang" failure sits on the connection, longer than the default request timeout
+ */
+const HANG_TIME: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug)]
+struct HashRequest {
+    username: String,
+    password: String,
+    #[serde(default)]
+    threadid: u32,
+    nonce: Option<Value>,
+}
+
+/** POST /fake/fail, the next `count` api calls fail like `mode` says
+ */
+#[derive(Deserialize, Debug)]
+struct FailRequest {
+    mode: Failure,
+    #[serde(default = "default_fail_count")]
+    count: u32,
+}
+
+fn default_fail_count() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Failure {
+    /** http 500
+     */
+    Error,
+    /** 200 with something thats not json
+     */
+    BadJson,
+    /** never answers, for timeouts
+     */
+    Hang,
+    /** hangs up without answering
+     */
+    Close,
+    /** says the password is wrong
+     */
+    Auth,
+    /** says the hash expired and hands out a new one
+     */
+    Stale,
+}
+
+/** POST /fake/rotate, gives the account a new hash like the real one does when another rig wins
+ */
+#[derive(Deserialize, Debug)]
+struct RotateRequest {
+    username: String,
+    #[serde(default)]
+    threadid: u32,
+}
+
+struct State {
+    /** chance of an `Error` failure on any call, on top of the queued ones
+     */
+    fail_rate: f64,
+    failures: Vec<Failure>,
+    passwords: HashMap<String, String>,
+    balances: HashMap<String, u32>,
+    challenges: HashMap<(String, u32), String>,
+}
+
+impl State {
+    fn challenge(&mut self, username: &str, thread_id: u32) -> String {
+        self.challenges.entry((username.to_string(), thread_id))
+            .or_insert_with(new_challenge)
+            .clone()
+    }
+
+    fn next_failure(&mut self) -> Option<Failure> {
+        if !self.failures.is_empty() {
+            return Some(self.failures.remove(0));
+        }
+        if (helpers::random() as f64 / u64::MAX as f64) < self.fail_rate {
+            return Some(Failure::Error);
+        }
+
+        return None;
+    }
+}
+
+/** `fakeserver [address] [fail rate]`, never returns
+ */
+pub fn run(args: &[String]) {
+    let address = args.first().map(|a| a.as_str()).unwrap_or("127.0.0.1:8080");
+    let fail_rate = args.get(1).map(|f| f.parse().expect("fail rate has to be between 0 and 1")).unwrap_or(0.0);
+
+    let state = Arc::new(Mutex::new(State {
+        fail_rate,
+        failures: Vec::new(),
+        passwords: HashMap::new(),
+        balances: HashMap::new(),
+        challenges: HashMap::new(),
+    }));
+
+    let listener = TcpListener::bind(address).expect("couldnt bind the fake server");
+    println!("fake gabserver on http://{}, {} bits of difficulty, {} fail rate", address, DIFFICULTY_BITS, fail_rate);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let state = state.clone();
+
+        thread::spawn(move || {
+            if let Err(err) = serve(stream, &state) {
+                println!("connection went bad: {}", err);
+            }
+        });
+    }
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("").to_string();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let (status, response) = match path.as_str() {
+        "/v1/userhashget" | "/v1/userhashset" => {
+            let failure = state.lock().unwrap().next_failure();
+            match failure {
+                Some(Failure::Error) => (500, String::from("{\"status\":\"internal error\"}")),
+                Some(Failure::BadJson) => (200, String::from("<html>not json</html>")),
+                Some(Failure::Hang) => {
+                    thread::sleep(HANG_TIME);
+                    return Ok(());
+                },
+                Some(Failure::Close) => return Ok(()),
+                Some(Failure::Auth) => (401, json!({ "status": "invalid credentials" }).to_string()),
+                Some(Failure::Stale) => stale(state, &body),
+                None => api(state, &path, &body),
+            }
+        },
+        "/fake/fail" => match serde_json::from_slice::<FailRequest>(&body) {
+            Ok(fail) => {
+                let mut state = state.lock().unwrap();
+                for _ in 0..fail.count {
+                    state.failures.push(fail.mode);
+                }
+                (200, json!({ "status": "success", "queued": state.failures.len() }).to_string())
+            },
+            Err(err) => (400, json!({ "status": err.to_string() }).to_string()),
+        },
+        "/fake/rotate" => match serde_json::from_slice::<RotateRequest>(&body) {
+            Ok(rotate) => {
+                let hash = new_challenge();
+                state.lock().unwrap().challenges.insert((rotate.username, rotate.threadid), hash.clone());
+                (200, json!({ "status": "success", "hash": hash }).to_string())
+            },
+            Err(err) => (400, json!({ "status": err.to_string() }).to_string()),
+        },
+        "/fake/balances" => (200, json!(state.lock().unwrap().balances).to_string()),
+        _ => (404, json!({ "status": "not found" }).to_string()),
+    };
+
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, response.len(), response
+    )?;
+
+    return stream.flush();
+}
+
+fn api(state: &Mutex<State>, path: &str, body: &[u8]) -> (u16, String) {
+    let request: HashRequest = match serde_json::from_slice(body) {
+        Ok(r) => r,
+        Err(err) => return (400, json!({ "status": err.to_string() }).to_string()),
+    };
+
+    let mut state = state.lock().unwrap();
+
+    // anyone can sign up, by asking with a new username
+    let password = state.passwords.entry(request.username.clone()).or_insert(request.password.clone());
+    if *password != request.password {
+        return (200, json!({ "status": "invalid credentials" }).to_string());
+    }
+
+    let hash = state.challenge(&request.username, request.threadid);
+    if path == "/v1/userhashget" {
+        return (200, json!({ "hash": hash, "status": "success" }).to_string());
+    }
+
+    // numbers get hashed as their decimal digits, strings as they are
+    let nonce = match request.nonce {
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::String(s)) => s,
+        _ => return (400, json!({ "status": "no nonce" }).to_string()),
+    };
+
+    // binary nonces come as the hex of the bytes that got hashed, which could just as well be a hex nonce, so try both
+    let mut messages = vec![(hash.clone() + &nonce).into_bytes()];
+    if let (16, Ok(bytes)) = (nonce.len(), hex::decode(&nonce)) {
+        messages.push([hash.as_bytes(), &bytes].concat());
+    }
+    let zeros = messages.iter()
+        .map(|message| leading_zeros(&sha1(message)))
+        .max()
+        .unwrap();
+
+    if zeros < DIFFICULTY_BITS {
+        println!("{}: wrong nonce {} for {}", request.username, nonce, hash);
+        return (200, json!({ "status": "invalid nonce" }).to_string());
+    }
+
+    let newhash = new_challenge();
+    state.challenges.insert((request.username.clone(), request.threadid), newhash.clone());
+    let balance = state.balances.entry(request.username.clone()).or_insert(0);
+    *balance += REWARD;
+    println!("{}: nonce {} for {} is good, balance {}", request.username, nonce, hash, balance);
+
+    return (200, json!({
+        "balance": *balance,
+        "reward": REWARD,
+        "newhash": newhash,
+        "status": "success",
+    }).to_string());
+}
+
+/** pretends the hash expired right before the call came in
+ */
+fn stale(state: &Mutex<State>, body: &[u8]) -> (u16, String) {
+    if let Ok(request) = serde_json::from_slice::<HashRequest>(body) {
+        state.lock().unwrap().challenges.insert((request.username, request.threadid), new_challenge());
+    }
+
+    return (200, json!({ "status": "hash expired" }).to_string());
+}
+
+/** whole message sha1, the miner only ever needs the last block so helpers doesnt have this
+ */
+fn sha1(message: &[u8]) -> [u32; 5] {
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    let mut state = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    for chunk in padded.chunks(64) {
+        let mut block = [0u32; 16];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            block[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        state = helpers::sha1_compress(state, &block);
+    }
+
+    return state;
+}
+
+/** the difficulty, leading zero bits of the whole digest
+ */
+fn leading_zeros(digest: &[u32; 5]) -> u32 {
+    digest.iter()
+        .position(|w| *w != 0)
+        .map(|i| i as u32 * 32 + digest[i].leading_zeros())
+        .unwrap_or(160)
+}
+
+/** 64 hex characters, same shape as the real ones
+ */
+fn new_challenge() -> String {
+    (0..4).map(|_| format!("{:016x}", helpers::random())).collect()
+}
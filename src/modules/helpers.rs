@@ -1,63 +1,69 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
 use std::num::Wrapping;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 /**
- * encodes u64 as an array of u32 elements, each 4 ascii digits
- * like:
- * [u32, u32, u32, u32]
- * where
- * u32: 00110001 00110000 00110000 00110000
- * ascii:  1        0         0        0
+ * fnv-1a of the machine's hostname
+ * DefaultHasher isnt stable between rust versions so the rig id would move around
  */
-pub fn nonce_to_u32arr(nonce: u64) -> [u32; 5] {
-    let mut digit;
-    let mut arr: [u32; 5] = [0; 5];
-
-    digit = (nonce / 1) % 10;
-    arr[4] += (digit | 0x30) as u32;
-    digit = (nonce / 10) % 10;
-    arr[4] += ((digit | 0x30) << 8) as u32;
-    digit = (nonce / 100) % 10;
-    arr[4] += ((digit | 0x30) << 16) as u32;
-    digit = (nonce / 1000) % 10;
-    arr[4] += ((digit | 0x30) << 24) as u32;
-
-    digit = (nonce / 10000) % 10;
-    arr[3] += (digit | 0x30) as u32;
-    digit = (nonce / 100000) % 10;
-    arr[3] += ((digit | 0x30) << 8) as u32;
-    digit = (nonce / 1000000) % 10;
-    arr[3] += ((digit | 0x30) << 16) as u32;
-    digit = (nonce / 10000000) % 10;
-    arr[3] += ((digit | 0x30) << 24) as u32;
-
-    digit = (nonce / 100000000) % 10;
-    arr[2] += ((digit | 0x30)) as u32;
-    digit = (nonce / 1000000000) % 10;
-    arr[2] += ((digit | 0x30) << 8) as u32;
-    digit = (nonce / 10000000000) % 10;
-    arr[2] += ((digit | 0x30) << 16) as u32;
-    digit = (nonce / 100000000000) % 10;
-    arr[2] += ((digit | 0x30) << 24) as u32;
-
-    digit = (nonce / 1000000000000) % 10;
-    arr[1] += ((digit | 0x30)) as u32;
-    digit = (nonce / 10000000000000) % 10;
-    arr[1] += ((digit | 0x30) << 8) as u32;
-    digit = (nonce / 100000000000000) % 10;
-    arr[1] += ((digit | 0x30) << 16) as u32;
-    digit = (nonce / 1000000000000000) % 10;
-    arr[1] += ((digit | 0x30) << 24) as u32;
-
-    digit = (nonce / 10000000000000000) % 10;
-    arr[0] += ((digit | 0x30)) as u32;
-    digit = (nonce / 100000000000000000) % 10;
-    arr[0] += ((digit | 0x30) << 8) as u32;
-    digit = (nonce / 1000000000000000000) % 10;
-    arr[0] += ((digit | 0x30) << 16) as u32;
-    digit = (nonce / 10000000000000000000) % 10;
-    arr[0] += ((digit | 0x30) << 24) as u32;
-
-    return arr;
+pub fn hostname_hash() -> u64 {
+    let hostname = env::var("COMPUTERNAME").ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .unwrap_or_default();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in hostname.trim().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    return hash;
+}
+
+/**
+ * `./name.json` for threadid 0 like always, `./name-3.json` for the others,
+ * with the account in there too when theres more than one
+ */
+pub fn thread_file(name: &str, account: Option<&str>, thread_id: u32) -> String {
+    let mut path = format!("./{}", name);
+
+    if let Some(account) = account {
+        path = path + "-" + &file_safe(account);
+    }
+    if thread_id != 0 {
+        path = path + "-" + &thread_id.to_string();
+    }
+
+    return path + ".json";
+}
+
+/** anything but letters, digits, _ and - comes out as %xx so a username cant walk out of the directory
+ */
+fn file_safe(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'-' => (b as char).to_string(),
+            _ => format!("%{:02x}", b),
+        })
+        .collect()
+}
+
+/** RandomState gets seeded randomly every time, good enough for jitter and the fake server without pulling in rand
+ */
+pub fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/** unix seconds, what the journals stamp things with
+ */
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
 
 pub fn to_u32(data: &str) -> u32 {
@@ -75,22 +81,34 @@ pub fn to_u32(data: &str) -> u32 {
 }
 
 pub fn sha1_prehash(hash: &str) -> [u32; 5] {
-    let mut words: [u32; 80] = [0; 80];
+    let mut block: [u32; 16] = [0; 16];
 
     for i in 0..16 {
-        words[i] = crate::modules::helpers::to_u32(&hash[(i*4)..(i*4) + 4])
+        block[i] = crate::modules::helpers::to_u32(&hash[(i*4)..(i*4) + 4])
     }
 
+    return sha1_compress([0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0], &block);
+}
+
+/**
+ * one round of sha1 over a 16 word block starting from `state`
+ * this is what the kernels do for the second block, so its the cpu reference for them
+ */
+pub fn sha1_compress(state: [u32; 5], block: &[u32; 16]) -> [u32; 5] {
+    let mut words: [u32; 80] = [0; 80];
+
+    words[..16].copy_from_slice(block);
+
     for i in 16..80 {
         words[i] = ROTL(words[i-3] ^ words[i-8] ^ words[i-14] ^ words[i-16], 1);
     }
 
     // allow wrapping additions on russy ahhhh
-    let h0: Wrapping<u32> = Wrapping(0x67452301);
-    let h1: Wrapping<u32> = Wrapping(0xEFCDAB89);
-    let h2: Wrapping<u32> = Wrapping(0x98BADCFE);
-    let h3: Wrapping<u32> = Wrapping(0x10325476);
-    let h4: Wrapping<u32> = Wrapping(0xC3D2E1F0);
+    let h0: Wrapping<u32> = Wrapping(state[0]);
+    let h1: Wrapping<u32> = Wrapping(state[1]);
+    let h2: Wrapping<u32> = Wrapping(state[2]);
+    let h3: Wrapping<u32> = Wrapping(state[3]);
+    let h4: Wrapping<u32> = Wrapping(state[4]);
 
     let k0: Wrapping<u32> = Wrapping(0x5A827999);
     let k1: Wrapping<u32> = Wrapping(0x6ED9EBA1);
@@ -134,4 +152,16 @@ pub fn sha1_prehash(hash: &str) -> [u32; 5] {
 }
 
 #[allow(non_snake_case)]
-pub fn ROTL(x: u32, n: u32) -> u32 {(x << n) | (x >> (32 - n))}
\ No newline at end of file
+pub fn ROTL(x: u32, n: u32) -> u32 {(x << n) | (x >> (32 - n))}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_file_stays_put() {
+        assert_eq!(thread_file("outbox", None, 0), "./outbox.json");
+        assert_eq!(thread_file("outbox", Some("gab_1-x"), 3), "./outbox-gab_1-x-3.json");
+        assert_eq!(thread_file("outbox", Some("../x"), 0), "./outbox-%2e%2e%2fx.json");
+        assert_eq!(thread_file("scanned", Some("a\\b c"), 0), "./scanned-a%5cb%20c.json");
+    }
+}
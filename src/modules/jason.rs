@@ -4,15 +4,265 @@ use std::io::BufReader;
 use serde::Deserialize;
 use serde_json;
 
+use crate::modules::network::ratelimit::SubmitLimit;
+use crate::modules::network::retry::RetryPolicy;
+use crate::modules::network::tls::TlsVersion;
+use crate::modules::nonce::NonceEncoding;
+use crate::modules::order::SearchOrder;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Account {
+    pub username: String,
+    pub password: String,
+    /** share of the gpu, or of the time when rotating
+     */
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/** a server or mirror, or our own relay
+ */
+#[derive(Deserialize, Debug, Clone)]
+pub struct Server {
+    pub url: String,
+    /** lowest gets used while its up
+     */
+    #[serde(default)]
+    pub priority: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountSplit {
+    /** every account mines all the time on its share of the gpu threads
+     */
+    #[default]
+    Devices,
+    /** one account at a time gets the whole gpu, for its share of `rotate_period_s`
+     */
+    Rotate,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ConfigFile {
+    /** the single account setup, goes into `accounts` if thats empty
+     */
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+    #[serde(default)]
+    pub account_split: AccountSplit,
+    #[serde(default = "default_rotate_period_s")]
+    pub rotate_period_s: u64,
     pub lobotomize: bool,
+    /** server side threadids, each one is its own chain of hashes and gets mined side by side
+     */
+    #[serde(default = "default_thread_ids")]
+    pub thread_ids: Vec<u32>,
+    #[serde(default)]
+    pub nonce_encoding: NonceEncoding,
+    /** defaults to a hash of the hostname
+     */
+    pub rig_id: Option<u64>,
+    #[serde(default = "default_rig_digits")]
+    pub rig_digits: u32,
+    #[serde(default)]
+    pub search_order: SearchOrder,
+    /** feistel key, defaults to the rig id
+     */
+    pub search_key: Option<u64>,
+    /** how long each worker aims for a round of dispatches to take
+     */
+    #[serde(default = "default_batch_target_ms")]
+    pub batch_target_ms: u64,
+    /** the single server setup, goes into `servers` if thats empty
+     */
+    #[serde(default = "default_server_url")]
+    pub server_url: String,
+    #[serde(default)]
+    pub servers: Vec<Server>,
+    /** failed calls in a row before moving on to the next server
+     */
+    #[serde(default = "default_failover_after")]
+    pub failover_after: u32,
+    /** how often to see if a server thats down is back, 0 means never
+     */
+    #[serde(default = "default_health_check_s")]
+    pub health_check_s: u64,
+    #[serde(default = "default_hash_get_path")]
+    pub hash_get_path: String,
+    #[serde(default = "default_hash_set_path")]
+    pub hash_set_path: String,
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /** whole request including reading the response
+     */
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /** http://, https:// or socks5:// url every server call goes through, without it the usual HTTPS_PROXY env vars apply
+     */
+    pub proxy: Option<String>,
+    /** hosts that skip `proxy`, same format as NO_PROXY
+     */
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    /** pem files with root certificates to trust on top of the system ones, for proxies that look inside tls
+     */
+    #[serde(default)]
+    pub ca_certs: Vec<String>,
+    /** pem certificate chain and its pkcs8 key, for when the proxy or server wants to know who we are
+     */
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub tls_min_version: Option<TlsVersion>,
+    /**
+     * hex sha256 of the server's public key (SubjectPublicKeyInfo), any one of them has to be in its chain or nothing gets sent
+     * `openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`
+     */
+    #[serde(default)]
+    pub pins: Vec<String>,
+    #[serde(default = "default_hash_get_retry")]
+    pub hash_get_retry: RetryPolicy,
+    #[serde(default = "default_submit_retry")]
+    pub submit_retry: RetryPolicy,
+    /** most submissions an account gets to make in a while, across all its threadids. none means no limit
+     */
+    pub submit_limit: Option<SubmitLimit>,
+    /** how often to check if the server changed the hash on its own, 0 turns it off
+     */
+    #[serde(default = "default_challenge_poll_ms")]
+    pub challenge_poll_ms: u64,
+    /** where `gpu-miner proxy` listens for miners
+     */
+    #[serde(default = "default_work_proxy_listen")]
+    pub work_proxy_listen: String,
+    /** host:port of a work proxy to mine for instead of the server, no credentials needed then
+     */
+    pub work_proxy: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_rotate_period_s() -> u64 {
+    3600
+}
+
+fn default_thread_ids() -> Vec<u32> {
+    vec![0]
+}
+
+fn default_rig_digits() -> u32 {
+    2
+}
+
+fn default_batch_target_ms() -> u64 {
+    100
+}
+
+fn default_server_url() -> String {
+    String::from("https://gabserver.eu")
+}
+
+fn default_failover_after() -> u32 {
+    3
 }
+
+fn default_health_check_s() -> u64 {
+    60
+}
+
+fn default_hash_get_path() -> String {
+    String::from("/v1/userhashget")
+}
+
+fn default_hash_set_path() -> String {
+    String::from("/v1/userhashset")
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_request_timeout_ms() -> u64 {
+    30000
+}
+
+fn default_user_agent() -> String {
+    String::from(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+}
+
+fn default_hash_get_retry() -> RetryPolicy {
+    RetryPolicy {
+        attempts: 8,
+        base_delay_ms: 500,
+        max_delay_ms: 30000,
+    }
+}
+
+/** a find goes stale fast, dont sit on it for ages
+ */
+fn default_submit_retry() -> RetryPolicy {
+    RetryPolicy {
+        attempts: 5,
+        base_delay_ms: 250,
+        max_delay_ms: 5000,
+    }
+}
+
+fn default_challenge_poll_ms() -> u64 {
+    15000
+}
+
+fn default_work_proxy_listen() -> String {
+    String::from("0.0.0.0:3333")
+}
+
 pub fn read_config() -> ConfigFile {
     let file = File::open("./config.json").expect("no config.json idor");
     let reader = BufReader::new(file);
 
-    serde_json::from_reader(reader).expect("jason parse err")
+    let mut config: ConfigFile = serde_json::from_reader(reader).expect("jason parse err");
+    if config.thread_ids.is_empty() {
+        panic!("thread_ids is empty, nothing to mine");
+    }
+
+    // the work proxy has the accounts
+    if config.accounts.is_empty() && config.work_proxy.is_none() {
+        if config.username.is_empty() {
+            panic!("no username or accounts in config.json");
+        }
+        config.accounts.push(Account {
+            username: config.username.clone(),
+            password: config.password.clone(),
+            weight: 1,
+        });
+    }
+    if config.servers.is_empty() {
+        config.servers.push(Server {
+            url: config.server_url.clone(),
+            priority: 0,
+        });
+    }
+    if config.accounts.iter().any(|a| a.weight == 0) {
+        panic!("account weights have to be at least 1");
+    }
+    if config.client_cert.is_some() != config.client_key.is_some() {
+        panic!("client_cert and client_key go together, set both or neither");
+    }
+    // native-tls cant be told to require 1.3, only the rustls setup pins use can
+    if config.tls_min_version == Some(TlsVersion::Tls13) && config.pins.is_empty() {
+        panic!("tls_min_version 1.3 only works together with pins, use 1.2 or pin the server's key");
+    }
+    if let Some(pin) = config.pins.iter().find(|p| p.len() != 64 || !p.chars().all(|c| c.is_ascii_hexdigit())) {
+        panic!("pin {} isnt a hex sha256", pin);
+    }
+
+    return config;
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+// every payout the server told us about, so theres a record of what the rigs earned and when
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json;
+
+use crate::modules::helpers;
+
+/** one json entry per line, only ever appended to so miners and a proxy in the same directory can share it
+ */
+const PATH: &str = "./ledger.jsonl";
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /** unix seconds
+     */
+    pub at: u64,
+    pub account: String,
+    pub thread_id: u32,
+    pub reward: u32,
+    /** what the server said the account has after this reward
+     */
+    pub balance: u32,
+    /**
+     * how far the balance moved past what the last entry and this reward add up to.
+     * other rigs or a payout on the account do that too, so its a hint not proof
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mismatch: Option<i64>,
+}
+
+/** theres nothing to keep in memory, the file is the ledger. the mutex around it keeps a process's threads in line
+ */
+pub struct Ledger {
+    path: String,
+}
+
+impl Ledger {
+    pub fn open() -> Ledger {
+        Ledger {
+            path: String::from(PATH),
+        }
+    }
+
+    /** everything thats in the file, a line a crash cut short gets skipped
+     */
+    fn entries(&self) -> Vec<Entry> {
+        return parse(&fs::read_to_string(&self.path).unwrap_or_default());
+    }
+
+    pub fn record(&self, account: &str, thread_id: u32, reward: u32, balance: u32) {
+        let text = fs::read_to_string(&self.path).unwrap_or_default();
+
+        // whoever wrote last, this process or another one, is what the balance should follow on from
+        let mismatch = parse(&text)
+            .iter()
+            .rev()
+            .find(|e| e.account == account)
+            .map(|last| balance as i64 - (last.balance as i64 + reward as i64))
+            .filter(|off| *off != 0);
+
+        if let Some(off) = mismatch {
+            println!("{}: balance is {} but the ledger says {}, off by {}", account, balance, balance as i64 - off, off);
+        }
+
+        let entry = Entry {
+            at: helpers::now(),
+            account: account.to_string(),
+            thread_id,
+            reward,
+            balance,
+            mismatch,
+        };
+        // one write per line so appends from different processes dont interleave
+        let mut line = serde_json::to_string(&entry).unwrap() + "\n";
+        // a crash cut the last line short, dont glue this one onto it
+        if !text.is_empty() && !text.ends_with('\n') {
+            line.insert(0, '\n');
+        }
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+
+        if let Err(err) = written {
+            println!("couldnt write {}: {}", self.path, err);
+        }
+    }
+
+    /** `gpu-miner earnings`, per account totals and rates
+     */
+    pub fn print_summary(&self) {
+        print!("{}", self.summary(helpers::now()));
+    }
+
+    fn summary(&self, now: u64) -> String {
+        let entries = self.entries();
+        if entries.is_empty() {
+            return format!("nothing in {} yet\n", self.path);
+        }
+
+        let mut accounts: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+        for entry in &entries {
+            accounts.entry(&entry.account).or_default().push(entry);
+        }
+
+        let mut out = String::new();
+        for (account, entries) in accounts {
+            let first = entries.first().unwrap();
+            let last = entries.last().unwrap();
+            let total: u64 = entries.iter().map(|e| e.reward as u64).sum();
+            let since = |secs: u64| -> u64 {
+                entries.iter().filter(|e| now.saturating_sub(e.at) < secs).map(|e| e.reward as u64).sum()
+            };
+
+            // over the whole history, at least an hour of it so one early payout doesnt look like a fortune
+            let span = now.saturating_sub(first.at).max(HOUR) as f64;
+            let mismatches: Vec<i64> = entries.iter().filter_map(|e| e.mismatch).collect();
+
+            out += &format!("{}:\n", account);
+            out += &format!("  {} rewards, {} total, balance {} ({}s ago)\n", entries.len(), total, last.balance, now.saturating_sub(last.at));
+            out += &format!("  {} in the last hour, {} in the last day\n", since(HOUR), since(DAY));
+            out += &format!("  {:.1}/hour, {:.1}/day since the first one\n", total as f64 * HOUR as f64 / span, total as f64 * DAY as f64 / span);
+            if !mismatches.is_empty() {
+                out += &format!("  balance didnt add up {} times, {} off in total\n", mismatches.len(), mismatches.iter().sum::<i64>());
+            }
+        }
+
+        return out;
+    }
+}
+
+fn parse(text: &str) -> Vec<Entry> {
+    return text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn ledger(name: &str) -> Ledger {
+        let path = env::temp_dir().join(format!("ledger-{}-{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        Ledger {
+            path: path.to_string_lossy().to_string(),
+        }
+    }
+
+    #[test]
+    fn entries_come_back_and_jumps_get_flagged() {
+        let ledger = ledger("record");
+        ledger.record("a", 0, 10, 10);
+        ledger.record("b", 1, 5, 500);
+        ledger.record("a", 1, 10, 20);
+        // a crash halfway through a line
+        OpenOptions::new().append(true).open(&ledger.path).unwrap().write_all(b"{\"at\":1,\"acc").unwrap();
+        ledger.record("a", 0, 10, 40);
+        ledger.record("b", 1, 5, 495);
+
+        let entries = ledger.entries();
+        fs::remove_file(&ledger.path).unwrap();
+
+        let got: Vec<(&str, u32, u32, u32, Option<i64>)> = entries.iter()
+            .map(|e| (e.account.as_str(), e.thread_id, e.reward, e.balance, e.mismatch))
+            .collect();
+        assert_eq!(got, vec![
+            ("a", 0, 10, 10, None),
+            ("b", 1, 5, 500, None),
+            ("a", 1, 10, 20, None),
+            ("a", 0, 10, 40, Some(10)),
+            ("b", 1, 5, 495, Some(-10)),
+        ]);
+    }
+
+    #[test]
+    fn summary_adds_up_per_account() {
+        let ledger = ledger("summary");
+        assert_eq!(ledger.summary(0), format!("nothing in {} yet\n", ledger.path));
+
+        let now = 10 * DAY;
+        let lines = [
+            Entry { at: now - 2 * DAY, account: String::from("b"), thread_id: 0, reward: 100, balance: 100, mismatch: None },
+            Entry { at: now - 2 * HOUR, account: String::from("b"), thread_id: 0, reward: 20, balance: 130, mismatch: Some(10) },
+            Entry { at: now - 60, account: String::from("b"), thread_id: 1, reward: 4, balance: 134, mismatch: None },
+            Entry { at: now - 30, account: String::from("a"), thread_id: 0, reward: 7, balance: 7, mismatch: None },
+        ];
+        let text: String = lines.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        fs::write(&ledger.path, text).unwrap();
+
+        let summary = ledger.summary(now);
+        fs::remove_file(&ledger.path).unwrap();
+
+        assert_eq!(summary, [
+            "a:",
+            "  1 rewards, 7 total, balance 7 (30s ago)",
+            "  7 in the last hour, 7 in the last day",
+            "  7.0/hour, 168.0/day since the first one",
+            "b:",
+            "  3 rewards, 124 total, balance 134 (60s ago)",
+            "  4 in the last hour, 24 in the last day",
+            "  2.6/hour, 62.0/day since the first one",
+            "  balance didnt add up 1 times, 10 off in total",
+            "",
+        ].join("\n"));
+    }
+}
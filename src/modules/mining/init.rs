@@ -85,4 +85,22 @@ pub fn init_device(instance: Arc<Instance>) -> (Arc<Device>, IntoIter<Arc<Queue>
     .unwrap();
 
     return (device, queues.collect::<Vec<Arc<Queue>>>().into_iter())
-}
\ No newline at end of file
+}
+
+/** a device and its queues, opened once and shared by every Minoer made on it
+ */
+pub struct Gpu {
+    pub device: Arc<Device>,
+    pub queues: Vec<Arc<Queue>>,
+}
+
+impl Gpu {
+    pub fn open() -> Gpu {
+        let (device, queues) = init_device(init_library());
+
+        Gpu {
+            device,
+            queues: queues.collect(),
+        }
+    }
+}
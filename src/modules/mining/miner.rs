@@ -1,9 +1,13 @@
+use std::collections::HashMap;
 use std::ops::Range;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::thread;
+use std::time::Duration;
 
 use vulkano::buffer::Buffer;
 use vulkano::buffer::Subbuffer;
@@ -22,6 +26,7 @@ use vulkano::device::Device;
 use vulkano::memory::allocator::MemoryTypeFilter;
 use vulkano::memory::allocator::AllocationCreateInfo;
 use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::memory::MemoryHeapFlags;
 
 use vulkano::pipeline::Pipeline;
 use vulkano::pipeline::PipelineLayout;
@@ -30,26 +35,97 @@ use vulkano::pipeline::PipelineShaderStageCreateInfo;
 use vulkano::pipeline::compute::ComputePipeline;
 use vulkano::pipeline::compute::ComputePipelineCreateInfo;
 use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::shader::SpecializationConstant;
 
-use crate::modules::helpers;
+use super::init::Gpu;
+use super::tune;
+use super::tune::Profile;
+use super::tune::ShaderVariant;
 use super::worker::Worker;
 use super::MinoerControlType;
-use super::MinoeringResult;
+use super::MinoerEvent;
+use super::MinoerJob;
+use super::INPUT_SIZE;
+use super::OUTPUT_WORDS;
+use crate::modules::nonce::NonceEncoding;
+use crate::modules::order::Permutation;
+use crate::modules::order::SearchOrder;
+use crate::modules::progress;
 
 pub struct Minoer {
     control_senders: Vec<Sender<MinoerControlType>>,
     threads: usize,
+    /** bumped on every mine and stop, threads drop whatever they're doing when it moves
+     */
+    generation: Arc<AtomicU64>,
+    job: Option<MinoerJob>,
+    /** unassigned logical dispatches per thread, chunks get cut off the front
+     */
+    queues: Vec<Vec<Range<u64>>>,
+    busy: Vec<bool>,
+    /** first hash word has to be zero under this to count as a find
+     */
+    mask: u32,
+    /** max_dispatches is what fit in memory, not what was asked for
+     */
+    profile: Profile,
 }
 
-pub const DISPATCH_SIZE: u32 = 65536;
+/** a find needs the whole first word of the hash to be zero
+ */
+pub const DIFFICULTY_MASK: u32 = 0xffffffff;
+
+/** most dispatches a thread gets per job
+ */
+pub const THREAD_DISPATCHES: u64 = 99999;
+
+/** dispatches handed to a thread at a time
+ */
+pub const CHUNK_DISPATCHES: u64 = 1024;
+
+/** with more lanes than queues, threads per lane so the weights still have something to split
+ */
+const LANE_THREADS: usize = 4;
 
 impl Minoer {
-    pub fn new(max_dispatches: usize, result_sender: Sender<MinoeringResult>) -> Minoer {
-        let instance = crate::modules::mining::init::init_library();
+    /**
+     * one Minoer per sender, all on the same device with the queues dealt out between them by `weights`
+     * with fewer queues than senders the queues get shared by several threads each, dealt out by weight too
+     * uses the autotuned profile for this device if theres one, otherwise the defaults
+     * `max_dispatches` is an upper bound, it gets cut down to what fits in device memory
+     */
+    pub fn split(max_dispatches: usize, batch_target: Duration, result_senders: Vec<Sender<MinoerEvent>>, weights: &[u32]) -> Vec<Minoer> {
+        Minoer::create(&Gpu::open(), None, max_dispatches, batch_target, result_senders, weights)
+    }
 
-        let (device, mut queues) = crate::modules::mining::init::init_device(instance);
-    
-        let ocs = crate::modules::mining::shader::cs::load(device.clone())
+    /** on a gpu thats already open, autotune goes through a lot of these and only the pipeline and buffers change
+     */
+    pub fn with_profile(gpu: &Gpu, profile: Profile, batch_target: Duration, result_sender: Sender<MinoerEvent>) -> Minoer {
+        Minoer::create(gpu, Some(profile), profile.max_dispatches, batch_target, vec![result_sender], &[1]).pop().unwrap()
+    }
+
+    fn create(gpu: &Gpu, profile: Option<Profile>, max_dispatches: usize, batch_target: Duration, result_senders: Vec<Sender<MinoerEvent>>, weights: &[u32]) -> Vec<Minoer> {
+        let device = gpu.device.clone();
+        let queues: Vec<Arc<Queue>> = gpu.queues.clone();
+
+        let profile = match profile {
+            Some(p) => p,
+            None => match tune::cached_profile(device.physical_device()) {
+                Some(p) => {
+                    println!("using tuned profile: {:?}", p);
+                    p
+                },
+                None => Profile::default_for(max_dispatches),
+            },
+        };
+
+        let module = match profile.variant {
+            ShaderVariant::Looped => crate::modules::mining::shader::cs::load(device.clone()),
+            ShaderVariant::Unrolled => crate::modules::mining::shader::ocs::load(device.clone()),
+        };
+        let ocs = module
+            .unwrap()
+            .specialize(HashMap::from([(0, SpecializationConstant::U32(profile.workgroup_size))]))
             .unwrap()
             .entry_point("main")
             .unwrap();
@@ -78,15 +154,36 @@ impl Minoer {
             Default::default(),
         ));
 
-        let mut control_senders = Vec::new();
-        let queue_amount = queues.len();
+        let lanes = result_senders.len();
+        let thread_amount = lane_threads(queues.len(), lanes);
+        let lane_of = deal(thread_amount, weights);
+        let mut control_senders: Vec<Vec<Sender<MinoerControlType>>> = vec![Vec::new(); lanes];
+        let generations: Vec<Arc<AtomicU64>> = (0..lanes).map(|_| Arc::new(AtomicU64::new(0))).collect();
+
+        // every pre-recorded dispatch owns an output buffer and its staging copy,
+        // keep all of them inside half of the biggest device local heap
+        let heap_size = device.physical_device().memory_properties().memory_heaps.iter()
+            .filter(|h| h.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|h| h.size)
+            .max()
+            .unwrap_or(0);
+        let output_words = profile.invocations as u64 * OUTPUT_WORDS as u64;
+        let dispatch_memory = output_words * 4 * 2;
+        let memory_dispatches = (heap_size / 2 / thread_amount as u64 / dispatch_memory).max(1) as usize;
+        let max_dispatches = profile.max_dispatches.min(memory_dispatches);
+        let profile = Profile {
+            max_dispatches,
+            ..profile
+        };
+        println!("{} threads over {} queues, up to {} dispatches each", thread_amount, queues.len(), max_dispatches);
         
-        for _ in 0..queue_amount {
+        for t in 0..thread_amount {
             let (control_sender, control_receiver) = mpsc::channel::<MinoerControlType>();
             let mut input_staging_buffers = Vec::new();
             let mut output_staging_buffers = Vec::new();
             let mut command_buffers = Vec::new();
-            let queue = queues.next().unwrap();
+            let queue = queues[t % queues.len()].clone();
+            let lane = lane_of[t];
             
             for _ in 0..max_dispatches {
 
@@ -101,7 +198,7 @@ impl Minoer {
                             | MemoryTypeFilter::PREFER_HOST,
                         ..Default::default()
                     },
-                    10,
+                    INPUT_SIZE as u64,
                 ).unwrap();
     
                 // Create a buffer in device-local memory.
@@ -117,7 +214,7 @@ impl Minoer {
                         memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                         ..Default::default()
                     },
-                    10,
+                    INPUT_SIZE as u64,
                 )
                 .unwrap();
     
@@ -132,7 +229,7 @@ impl Minoer {
                             | MemoryTypeFilter::PREFER_HOST,
                         ..Default::default()
                     },
-                    (DISPATCH_SIZE*64).into()
+                    output_words
                 ).unwrap();
     
                 // Create a buffer in device-local memory.
@@ -148,7 +245,7 @@ impl Minoer {
                         memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                         ..Default::default()
                     },
-                    (DISPATCH_SIZE*64).into(),
+                    output_words,
                 )
                 .unwrap();
     
@@ -197,7 +294,7 @@ impl Minoer {
                         0,
                         set,
                     ).unwrap()
-                    .dispatch([DISPATCH_SIZE, 1, 1]).unwrap()
+                    .dispatch([profile.workgroups(), 1, 1]).unwrap()
                     .copy_buffer(CopyBufferInfo::buffers(
                         output_buffer,
                         output_staging_buffer.clone()
@@ -209,41 +306,163 @@ impl Minoer {
                 output_staging_buffers.push(output_staging_buffer);
                 command_buffers.push(command_buffer);
             }
-            control_senders.push(control_sender);
+            control_senders[lane].push(control_sender);
 
             Minoer::spawn_thread(
+                control_senders[lane].len() - 1,
                 device.clone(),
                 queue,
                 input_staging_buffers,
                 output_staging_buffers,
                 command_buffers,
                 control_receiver,
-                result_sender.clone(),
+                result_senders[lane].clone(),
+                profile.invocations,
+                batch_target,
+                generations[lane].clone(),
             );
         }
         // println!("{}", queues.len());
 
-        let minoer = Minoer {
-            control_senders,
-            threads: queue_amount,
-        };
+        let minoers = control_senders.into_iter().zip(generations).map(|(control_senders, generation)| {
+            let threads = control_senders.len();
+
+            Minoer {
+                control_senders,
+                threads,
+                generation,
+                job: None,
+                queues: vec![Vec::new(); threads],
+                busy: vec![false; threads],
+                mask: DIFFICULTY_MASK,
+                profile,
+            }
+        }).collect();
+
+        return minoers;
+    }
+
+    /** lays out a job over the threads, `dispatches` is how many fit before running into the rig prefix
+     */
+    pub fn plan(&self, order: SearchOrder, key: u64, dispatches: u64) -> Permutation {
+        let total = (self.threads as u64 * THREAD_DISPATCHES).min(dispatches);
 
-        return minoer;
+        return Permutation::new(order, key, total, self.threads as u64);
     }
 
-    pub fn mine(&mut self, data: [u32; 10], nonce: u64) {
+    /** `scanned` are logical dispatch indices already done for this job, sorted and merged
+     */
+    pub fn mine(&mut self, data: [u32; INPUT_SIZE], nonce: u64, encoding: NonceEncoding, permutation: Permutation, scanned: &[Range<u64>]) {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+
+        self.job = Some(MinoerJob {
+            generation,
+            mask: self.mask,
+            data,
+            nonce,
+            encoding,
+            permutation,
+        });
+
+        for i in 0..self.threads {
+            self.queues[i] = progress::subtract(permutation.thread_range(i as u64), scanned);
+        }
         for i in 0..self.threads {
-            let range: u64 = (i*99999).try_into().unwrap();
-            let range_top: u64 = ((i+1)*99999).try_into().unwrap();
+            self.refill(i);
+        }
+    }
 
-            self.control_senders[i].send(
-                MinoerControlType::Start(data, nonce, range..range_top)
-            ).unwrap();
+    /**
+     * gives thread `thread` its next chunk, out of its own queue first and then
+     * stolen from whoever has the most left. false if theres nothing left to give
+     */
+    pub fn refill(&mut self, thread: usize) -> bool {
+        let chunk = match self.take_chunk(thread) {
+            Some(c) => Some(c),
+            None => {
+                let victim = (0..self.threads)
+                    .max_by_key(|t| self.queues[*t].iter().map(|r| r.end - r.start).sum::<u64>())
+                    .unwrap();
+                self.steal_chunk(victim)
+            },
+        };
+
+        let (chunk, job) = match (chunk, &self.job) {
+            (Some(c), Some(j)) => (c, j),
+            _ => {
+                self.busy[thread] = false;
+                return false;
+            },
+        };
+
+        self.control_senders[thread].send(
+            MinoerControlType::Start(job.clone(), chunk)
+        ).unwrap();
+        self.busy[thread] = true;
+
+        return true;
+    }
+
+    /** nothing is being mined and nothing is left to hand out
+     */
+    pub fn idle(&self) -> bool {
+        !self.busy.iter().any(|b| *b)
+    }
+
+    fn take_chunk(&mut self, thread: usize) -> Option<Range<u64>> {
+        let range = self.queues[thread].first_mut()?;
+        let chunk = range.start..(range.start + CHUNK_DISPATCHES).min(range.end);
+
+        range.start = chunk.end;
+        if range.start == range.end {
+            self.queues[thread].remove(0);
+        }
+
+        return Some(chunk);
+    }
+
+    fn steal_chunk(&mut self, thread: usize) -> Option<Range<u64>> {
+        let range = self.queues[thread].last_mut()?;
+        let chunk = range.end.saturating_sub(CHUNK_DISPATCHES).max(range.start)..range.end;
+
+        range.end = chunk.start;
+        if range.start == range.end {
+            self.queues[thread].pop();
         }
+
+        return Some(chunk);
     }
 
-    pub fn stop_mining(&self) {
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn invocations(&self) -> u32 {
+        self.profile.invocations
+    }
+
+    /** what this is actually running with
+     */
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /** difficulty for jobs started from now on
+     */
+    pub fn set_mask(&mut self, mask: u32) {
+        self.mask = mask;
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    pub fn stop_mining(&mut self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.job = None;
         for i in 0..self.threads {
+            self.queues[i].clear();
+            self.busy[i] = false;
             self.control_senders[i].send(
                 MinoerControlType::Stop
             ).unwrap();
@@ -251,64 +470,166 @@ impl Minoer {
     }
 
     fn spawn_thread(
+        index: usize,
         device: Arc<Device>,
         queue: Arc<Queue>,
         input_staging_buffers: Vec<Subbuffer<[u32]>>,
         output_staging_buffers: Vec<Subbuffer<[u32]>>,
         command_buffers: Vec<Arc<PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>>>,
         control_reciever: Receiver<MinoerControlType>,
-        result_sender: Sender<MinoeringResult>,
+        result_sender: Sender<MinoerEvent>,
+        invocations: u32,
+        batch_target: Duration,
+        generation: Arc<AtomicU64>,
     ) {
         thread::spawn(move || {
             let max_dispatches = command_buffers.len();
-            let mut worker = Worker::new(queue, device, input_staging_buffers, output_staging_buffers, command_buffers, max_dispatches);
-            let mut data: [u32; 10];
-            let mut nonce: u64;
+            let mut worker = Worker::new(queue, device, input_staging_buffers, output_staging_buffers, command_buffers, max_dispatches, invocations, batch_target, generation);
+            let mut job: MinoerJob;
             let mut range: Range<u64>;
 
             loop {
-                (data, nonce, range) = match control_reciever.recv() {
+                (job, range) = match control_reciever.recv() {
                     Ok(d) => match d {
-                        MinoerControlType::Stop => continue,
-                        MinoerControlType::Start(d, n, r) => {
+                        MinoerControlType::Stop => {
                             worker.reset();
-                            (d, n, r)
+                            continue
+                        },
+                        MinoerControlType::Start(j, r) => {
+                            worker.set_job(&j);
+                            (j, r)
                         },
                     },
                     Err(_) => break,
                 };
-                
-                for i in range {
-                    match control_reciever.try_recv() {
-                        Ok(d) => match d {
-                            MinoerControlType::Stop => break,
-                            MinoerControlType::Start(..) => {},
-                        },
-                        Err(e) => match e {
-                            mpsc::TryRecvError::Empty => {},
-                            mpsc::TryRecvError::Disconnected => return,
-                        },
-                    };
-    
-                    let nonce: u64 = nonce + i * 10_u64.pow(10);
-                    let nonce_arr = helpers::nonce_to_u32arr(nonce);
-                    data[5] = nonce_arr[0];
-                    data[6] = nonce_arr[1];
-                    data[7] = nonce_arr[2];
-                    data[8] = nonce_arr[3];
-                    data[9] = nonce_arr[4];
-
-                    worker.submit(data, nonce);
-                    if (i + 1) % max_dispatches as u64 == 0 && (i != 0 || max_dispatches == 1) {
+
+                let encoding = job.encoding;
+                let mut batch_start = range.start;
+                let mut cancelled = false;
+
+                for i in range.clone() {
+                    if worker.stale() {
+                        // let the in flight dispatches drain, nobody wants their results anymore
+                        worker.get_results();
+                        cancelled = true;
+                        break;
+                    }
+
+                    let nonce: u64 = job.nonce - job.nonce % encoding.stride() + job.permutation.get(i) * encoding.stride();
+                    encoding.write_block(nonce, &mut job.data[5..]);
+
+                    worker.submit(job.data, nonce);
+                    if worker.is_full() || i + 1 == range.end {
                         let results = worker.get_results();
 
                         for result in results {
-                            result_sender.send(result).unwrap();
+                            if result_sender.send(MinoerEvent::Found(result)).is_err() {
+                                return;
+                            }
+                        }
+                        if result_sender.send(MinoerEvent::Scanned(job.generation, batch_start..i + 1)).is_err() {
+                            return;
                         }
+                        batch_start = i + 1;
                     }
                 }
+
+                // nobody listening anymore, the miner got dropped
+                if !cancelled && result_sender.send(MinoerEvent::Exhausted(job.generation, index)).is_err() {
+                    return;
+                }
             }
         });
     }
 }
 
+/** every lane gets a thread, the rest go by weight. one thread per lane would ignore the weights
+ */
+fn lane_threads(queues: usize, lanes: usize) -> usize {
+    if lanes > 1 {
+        return queues.max(lanes * LANE_THREADS);
+    }
+
+    return queues;
+}
+
+/**
+ * which lane each of `threads` threads goes to, proportional to `weights` (largest remainder)
+ * every lane gets at least one, `threads` has to be at least `weights.len()`
+ */
+fn deal(threads: usize, weights: &[u32]) -> Vec<usize> {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    let spare = (threads - weights.len()) as u64;
+
+    let mut counts: Vec<usize> = weights.iter().map(|w| 1 + (spare * *w as u64 / total) as usize).collect();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by_key(|i| std::cmp::Reverse(spare * weights[*i] as u64 % total));
+
+    let mut left = threads - counts.iter().sum::<usize>();
+    for i in by_remainder.into_iter().cycle() {
+        if left == 0 {
+            break;
+        }
+        counts[i] += 1;
+        left -= 1;
+    }
+
+    // interleaved so every lane gets spread over the queues
+    let mut lanes = Vec::with_capacity(threads);
+    while lanes.len() < threads {
+        for (lane, count) in counts.iter_mut().enumerate() {
+            if *count > 0 {
+                *count -= 1;
+                lanes.push(lane);
+            }
+        }
+    }
+
+    return lanes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(lanes: &[usize], weights: &[u32]) -> Vec<usize> {
+        (0..weights.len()).map(|lane| lanes.iter().filter(|l| **l == lane).count()).collect()
+    }
+
+    #[test]
+    fn deal_splits_uneven_weights_by_largest_remainder() {
+        assert_eq!(counts(&deal(10, &[1, 1, 1]), &[1, 1, 1]), vec![4, 3, 3]);
+        assert_eq!(counts(&deal(7, &[3, 1]), &[3, 1]), vec![5, 2]);
+        assert_eq!(counts(&deal(8, &[5, 2, 1]), &[5, 2, 1]), vec![4, 2, 2]);
+
+        // interleaved, the first few threads already cover every lane
+        let lanes = deal(10, &[3, 1, 1]);
+        assert_eq!(&lanes[..3], &[0, 1, 2]);
+        assert_eq!(lanes.len(), 10);
+    }
+
+    #[test]
+    fn one_queue_still_splits_by_weight() {
+        assert_eq!(lane_threads(1, 1), 1);
+        assert_eq!(deal(lane_threads(1, 1), &[7]), vec![0]);
+
+        let weights = [2, 1, 1];
+        let threads = lane_threads(1, weights.len());
+        assert_eq!(threads, 3 * LANE_THREADS);
+        assert_eq!(counts(&deal(threads, &weights), &weights), vec![6, 3, 3]);
+    }
+
+    #[test]
+    fn more_lanes_than_queues_gives_every_lane_threads() {
+        let weights = [1, 1, 1, 1, 100];
+        let threads = lane_threads(2, weights.len());
+        let counts = counts(&deal(threads, &weights), &weights);
+
+        assert_eq!(counts.iter().sum::<usize>(), threads);
+        assert!(counts.iter().all(|c| *c >= 1), "{:?}", counts);
+        assert!(counts[4] > counts[0] * 4, "{:?}", counts);
+
+        // more queues than the lanes need, they all get used
+        assert_eq!(lane_threads(16, 2), 16);
+    }
+}
@@ -1,28 +1,83 @@
 use std::ops::Range;
+use std::time::Instant;
+
+use crate::modules::nonce::NonceEncoding;
+use crate::modules::order::Permutation;
 
 pub mod miner;
+pub mod tune;
 mod init;
 mod shader;
 mod worker;
 
+/** words in each dispatch's input buffer: prehash state, second block, kernel parameters
+ */
+pub const INPUT_SIZE: usize = 24;
+
+/** words each invocation writes back: the hash and its invocation id
+ */
+pub const OUTPUT_WORDS: usize = 6;
+
 pub struct MinoeringResult {
+    pub generation: u64,
     pub nonce: u64,
+    pub encoding: NonceEncoding,
     pub hashes: Vec<u32>,
     pub minoers_mined: usize,
+    /** when the worker read it back from the gpu
+     */
+    pub found_at: Instant,
 }
 
 impl MinoeringResult {
-    pub fn new(nonce: u64, hashes: Vec<u32>, minoers_mined: usize) -> MinoeringResult{
+    pub fn new(generation: u64, nonce: u64, encoding: NonceEncoding, hashes: Vec<u32>, minoers_mined: usize) -> MinoeringResult{
         MinoeringResult {
+            generation,
             nonce,
+            encoding,
             hashes,
             minoers_mined,
+            found_at: Instant::now(),
         }
     }
 }
 
+/** what the worker threads send back, each tagged with the generation of the job it belongs to
+ */
+pub enum MinoerEvent {
+    Found(MinoeringResult),
+    /** logical dispatch indices of the job that are fully checked
+     */
+    Scanned(u64, Range<u64>),
+    /** thread finished its chunk and wants another
+     */
+    Exhausted(u64, usize),
+}
+
+impl MinoerEvent {
+    pub fn generation(&self) -> u64 {
+        match self {
+            MinoerEvent::Found(r) => r.generation,
+            MinoerEvent::Scanned(g, _) => *g,
+            MinoerEvent::Exhausted(g, _) => *g,
+        }
+    }
+}
+
+/** everything a thread needs to mine one job, only the chunk changes between Starts
+ */
+#[derive(Clone)]
+pub struct MinoerJob {
+    pub generation: u64,
+    pub mask: u32,
+    pub data: [u32; INPUT_SIZE],
+    pub nonce: u64,
+    pub encoding: NonceEncoding,
+    pub permutation: Permutation,
+}
+
 #[derive(Clone)]
 pub enum MinoerControlType {
     Stop,
-    Start([u32; 10], u64, Range<u64>)
+    Start(MinoerJob, Range<u64>)
 }
\ No newline at end of file
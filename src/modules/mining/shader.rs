@@ -4,10 +4,11 @@ pub mod cs {
         src: r"
             #version 450
 
-            layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+            // workgroup size comes from the tuning profile
+            layout(local_size_x_id = 0, local_size_y = 1, local_size_z = 1) in;
 
             layout(set = 0, binding = 0) buffer input_buffer {
-                uint input_data[10];
+                uint input_data[24];
             };
 
             layout(set = 0, binding = 1) buffer output_buffer {
@@ -16,33 +17,15 @@ pub mod cs {
 
             #define ROTL(x, n) ((x << n) | (x >> (32 - n)))
 
-            uvec4 add(uvec4 data, uint nonce) {
-                uint digit = 0;
-
-                digit = (nonce / 1) % 10;
-                data.w += digit;
-                digit = (nonce / 10) % 10;
-                data.w += digit << 8;
-                digit = (nonce / 100) % 10;
-                data.w += digit << 16;
-                digit = (nonce / 1000) % 10;
-                data.w += digit << 24;
-
-                digit = (nonce / 10000) % 10;
-                data.z += digit;
-                digit = (nonce / 100000) % 10;
-                data.z += digit << 8;
-                digit = (nonce / 1000000) % 10;
-                data.z += digit << 16;
-                digit = (nonce / 10000000) % 10;
-                data.z += digit << 24;
-
-                digit = (nonce / 100000000) % 10;
-                data.y += digit;
-                digit = (nonce / 1000000000) % 10;
-                data.y += digit << 8;
-
-                return data;
+            // digit to ascii, 0-9 then a-z then A-Z
+            uint to_char(uint digit) {
+                if (digit < 10) {
+                    return 0x30 + digit;
+                }
+                if (digit < 36) {
+                    return 0x57 + digit;
+                }
+                return 0x1d + digit;
             }
 
             const uint h0 = 0x67452301;
@@ -60,18 +43,23 @@ pub mod cs {
                 uint invocationID = gl_GlobalInvocationID.x;
                 uint words[80];
 
-                for (int i = 6; i < 15; i++) {
-                    words[i] = 0;
+                for (int i = 0; i < 16; i++) {
+                    words[i] = input_data[5 + i];
                 }
 
-                uvec4 res = add(uvec4(input_data[6], input_data[7], input_data[8], input_data[9]), invocationID);
-                words[0] = input_data[5];
-                words[1] = res[0];
-                words[2] = res[1];
-                words[3] = res[2];
-                words[4] = res[3];
-                words[5] = 0x80000000;
-                words[15] = 672;
+                uint radix = input_data[21];
+                uint width = input_data[22];
+                uint digits = input_data[23];
+                uint rest = invocationID;
+                for (uint j = 0; j < digits; j++) {
+                    uint digit = rest % radix;
+                    rest /= radix;
+
+                    // raw nonces are little-endian, text nonces have the least significant digit last
+                    uint pos = radix == 256 ? j : width - 1 - j;
+                    uint ch = radix == 256 ? digit : to_char(digit);
+                    words[pos >> 2] |= ch << (24 - 8 * (pos & 3));
+                }
                 // words[4] = input_data[4];
                 // words[5] = input_data[5] + gl_GlobalInvocationID.x;
                 // words[6] = 0x80000000; // padding
@@ -135,10 +123,11 @@ pub mod ocs {
         src: r"
             #version 450
 
-            layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+            // workgroup size comes from the tuning profile
+            layout(local_size_x_id = 0, local_size_y = 1, local_size_z = 1) in;
 
             layout(set = 0, binding = 0) buffer input_buffer {
-                uint input_data[10];
+                uint input_data[24];
             };
 
             layout(set = 0, binding = 1) buffer output_buffer {
@@ -147,33 +136,15 @@ pub mod ocs {
 
             #define ROTL(x, n) ((x << n) | (x >> (32 - n)))
 
-            uvec4 add(uvec4 data, uint nonce) {
-                uint digit = 0;
-
-                digit = (nonce / 1) % 10;
-                data.w += digit;
-                digit = (nonce / 10) % 10;
-                data.w += digit << 8;
-                digit = (nonce / 100) % 10;
-                data.w += digit << 16;
-                digit = (nonce / 1000) % 10;
-                data.w += digit << 24;
-
-                digit = (nonce / 10000) % 10;
-                data.z += digit;
-                digit = (nonce / 100000) % 10;
-                data.z += digit << 8;
-                digit = (nonce / 1000000) % 10;
-                data.z += digit << 16;
-                digit = (nonce / 10000000) % 10;
-                data.z += digit << 24;
-
-                digit = (nonce / 100000000) % 10;
-                data.y += digit;
-                digit = (nonce / 1000000000) % 10;
-                data.y += digit << 8;
-
-                return data;
+            // digit to ascii, 0-9 then a-z then A-Z
+            uint to_char(uint digit) {
+                if (digit < 10) {
+                    return 0x30 + digit;
+                }
+                if (digit < 36) {
+                    return 0x57 + digit;
+                }
+                return 0x1d + digit;
             }
 
             const uint h0 = 0x67452301;
@@ -191,23 +162,36 @@ pub mod ocs {
                 uint invocationID = gl_GlobalInvocationID.x;
                 uint words[80];
 
-                uvec4 res = add(uvec4(input_data[6], input_data[7], input_data[8], input_data[9]), invocationID);
                 words[0] = input_data[5];
-                words[1] = res[0];
-                words[2] = res[1];
-                words[3] = res[2];
-                words[4] = res[3];
-                words[5] = 0x80000000;
-                words[6] = 0;
-                words[7] = 0;
-                words[8] = 0;
-                words[9] = 0;
-                words[10] = 0;
-                words[11] = 0;
-                words[12] = 0;
-                words[13] = 0;
-                words[14] = 0;
-                words[15] = 672;
+                words[1] = input_data[6];
+                words[2] = input_data[7];
+                words[3] = input_data[8];
+                words[4] = input_data[9];
+                words[5] = input_data[10];
+                words[6] = input_data[11];
+                words[7] = input_data[12];
+                words[8] = input_data[13];
+                words[9] = input_data[14];
+                words[10] = input_data[15];
+                words[11] = input_data[16];
+                words[12] = input_data[17];
+                words[13] = input_data[18];
+                words[14] = input_data[19];
+                words[15] = input_data[20];
+
+                uint radix = input_data[21];
+                uint width = input_data[22];
+                uint digits = input_data[23];
+                uint rest = invocationID;
+                for (uint j = 0; j < digits; j++) {
+                    uint digit = rest % radix;
+                    rest /= radix;
+
+                    // raw nonces are little-endian, text nonces have the least significant digit last
+                    uint pos = radix == 256 ? j : width - 1 - j;
+                    uint ch = radix == 256 ? digit : to_char(digit);
+                    words[pos >> 2] |= ch << (24 - 8 * (pos & 3));
+                }
 
                 words[16] = ROTL((words[13] ^ words[8] ^ words[2] ^ words[0]), 1);
                 words[17] = ROTL((words[14] ^ words[9] ^ words[3] ^ words[1]), 1);
@@ -275,11 +259,11 @@ pub mod ocs {
                 words[79] = ROTL((words[76] ^ words[71] ^ words[65] ^ words[63]), 1);
 
                 uint a, b, c, d, e, temp;
-                a = h0;
-                b = h1;
-                c = h2;
-                d = h3;
-                e = h4;
+                a = input_data[0];
+                b = input_data[1];
+                c = input_data[2];
+                d = input_data[3];
+                e = input_data[4];
 
                 temp = ROTL(a, 5)+((b&c)|((~b)&d))+e+k0+words[0];            
                 e=d;d=c;c=ROTL(b,30);b=a;a=temp;
@@ -0,0 +1,257 @@
+// finds the fastest kernel setup for whatever gpu this is and remembers it
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json;
+use vulkano::device::physical::PhysicalDevice;
+
+use crate::modules::helpers;
+use crate::modules::nonce::NonceEncoding;
+use crate::modules::order::SearchOrder;
+use super::init::Gpu;
+use super::miner::Minoer;
+use super::MinoerEvent;
+use super::INPUT_SIZE;
+
+const TUNE_CACHE_PATH: &str = "./tune.json";
+
+/** most invocations per dispatch, every nonce encoding's gpu digits cover this many
+ */
+pub const MAX_INVOCATIONS: u32 = 1 << 22;
+
+/** how long each candidate gets to mine
+ */
+const TUNE_TIME: Duration = Duration::from_secs(3);
+
+/** result filter while tuning, loose enough to get a bunch of finds per dispatch to check
+ */
+const TUNE_MASK: u32 = 0xffff0000;
+
+/** most finds per candidate that get checked on the cpu
+ */
+const TUNE_CHECKS: usize = 256;
+
+const TUNE_HASH: &str = "8deda67f452dc5de673a01fad1580ca4429bc166a4e3dc5d3911535616327e32";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShaderVariant {
+    /** shader::cs
+     */
+    Looped,
+    /** shader::ocs
+     */
+    Unrolled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Profile {
+    pub variant: ShaderVariant,
+    pub workgroup_size: u32,
+    /** invocations per dispatch, a multiple of workgroup_size
+     */
+    pub invocations: u32,
+    pub max_dispatches: usize,
+    /** what autotune measured, 0 if it never ran
+     */
+    pub hashrate: u64,
+}
+
+impl Profile {
+    /** what we ran before there was any tuning
+     */
+    pub fn default_for(max_dispatches: usize) -> Profile {
+        Profile {
+            variant: ShaderVariant::Looped,
+            workgroup_size: 64,
+            invocations: MAX_INVOCATIONS,
+            max_dispatches,
+            hashrate: 0,
+        }
+    }
+
+    pub fn workgroups(&self) -> u32 {
+        self.invocations / self.workgroup_size
+    }
+}
+
+/** device uuid plus driver version, a driver update can change whats fastest
+ */
+pub fn device_key(device: &PhysicalDevice) -> String {
+    let properties = device.properties();
+    let uuid = match properties.device_uuid {
+        Some(uuid) => hex::encode(uuid),
+        None => properties.device_name.clone(),
+    };
+
+    return uuid + "-" + &properties.driver_version.to_string();
+}
+
+/** biggest workgroup the kernel can be specialized to on this device
+ */
+pub fn max_workgroup_size(device: &PhysicalDevice) -> u32 {
+    let properties = device.properties();
+
+    return properties.max_compute_work_group_size[0].min(properties.max_compute_work_group_invocations);
+}
+
+fn read_cache() -> HashMap<String, Profile> {
+    match File::open(TUNE_CACHE_PATH) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+            println!("couldnt read {}: {}", TUNE_CACHE_PATH, err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+pub fn cached_profile(device: &PhysicalDevice) -> Option<Profile> {
+    read_cache().remove(&device_key(device))
+}
+
+fn save_profile(key: String, profile: Profile) {
+    let mut cache = read_cache();
+    cache.insert(key, profile);
+
+    // write then rename, a crash halfway through shouldnt cost every other gpu's profile
+    let tmp = TUNE_CACHE_PATH.to_string() + ".tmp";
+    let written = File::create(&tmp)
+        .map_err(|err| err.to_string())
+        .and_then(|file| serde_json::to_writer_pretty(file, &cache).map_err(|err| err.to_string()))
+        .and_then(|_| fs::rename(&tmp, TUNE_CACHE_PATH).map_err(|err| err.to_string()));
+
+    if let Err(err) = written {
+        println!("couldnt write {}: {}", TUNE_CACHE_PATH, err);
+    }
+}
+
+/**
+ * sweeps shader variant, workgroup size and invocations per dispatch first,
+ * then how many dispatches to keep in flight, and caches the fastest one that
+ * agrees with the cpu
+ */
+pub fn autotune(batch_target: Duration) {
+    // one device for the whole sweep, making and tearing down one per candidate is slow and some drivers hate it
+    let gpu = Gpu::open();
+    let key = device_key(gpu.device.physical_device());
+    let max_workgroup_size = max_workgroup_size(gpu.device.physical_device());
+    println!("tuning {}", key);
+
+    let mut best: Option<Profile> = None;
+
+    for variant in [ShaderVariant::Looped, ShaderVariant::Unrolled] {
+        for workgroup_size in [32, 64, 128, 256] {
+            if workgroup_size > max_workgroup_size {
+                continue;
+            }
+
+            for invocations in [MAX_INVOCATIONS / 4, MAX_INVOCATIONS / 2, MAX_INVOCATIONS] {
+                let candidate = Profile {
+                    variant,
+                    workgroup_size,
+                    invocations,
+                    max_dispatches: 8,
+                    hashrate: 0,
+                };
+                best = faster(best, run(&gpu, candidate, batch_target));
+            }
+        }
+    }
+
+    let shape = best.expect("nothing passed the cpu check, the kernels are broken on this device");
+    for max_dispatches in [2, 4, 16, 32] {
+        let candidate = Profile {
+            max_dispatches,
+            hashrate: 0,
+            ..shape
+        };
+        best = faster(best, run(&gpu, candidate, batch_target));
+    }
+
+    let best = best.unwrap();
+    println!("best: {:?}", best);
+    save_profile(key, best);
+}
+
+fn faster(best: Option<Profile>, candidate: Option<Profile>) -> Option<Profile> {
+    match (best, candidate) {
+        (Some(b), Some(c)) if c.hashrate > b.hashrate => Some(c),
+        (None, c) => c,
+        (b, _) => b,
+    }
+}
+
+/** mines a made up challenge for TUNE_TIME, None if the gpu got any hash wrong
+ */
+fn run(gpu: &Gpu, profile: Profile, batch_target: Duration) -> Option<Profile> {
+    println!("trying {:?} x{} with {} invocations, {} dispatches", profile.variant, profile.workgroup_size, profile.invocations, profile.max_dispatches);
+
+    let (sender, receiver) = mpsc::channel::<MinoerEvent>();
+    let mut miner = Minoer::with_profile(gpu, profile, batch_target, sender);
+
+    let prehash = helpers::sha1_prehash(TUNE_HASH);
+    let mut data: [u32; INPUT_SIZE] = [0; INPUT_SIZE];
+    data[..5].copy_from_slice(&prehash);
+
+    let encoding = NonceEncoding::Decimal;
+    let permutation = miner.plan(SearchOrder::Sequential, 0, encoding.dispatch_limit(0));
+    miner.set_mask(TUNE_MASK);
+    miner.mine(data, encoding.start(), encoding, permutation, &[]);
+
+    let started = Instant::now();
+    let mut dispatches: u64 = 0;
+    let mut checked = 0;
+
+    while started.elapsed() < TUNE_TIME {
+        let event = match receiver.recv_timeout(TUNE_TIME) {
+            Ok(e) => e,
+            Err(_) => break,
+        };
+
+        match event {
+            MinoerEvent::Found(result) => {
+                if checked >= TUNE_CHECKS {
+                    continue;
+                }
+                checked += 1;
+
+                let reference = helpers::sha1_compress(prehash, &encoding.message_block(result.nonce));
+                if result.hashes[..] != reference[..] || result.hashes[0] & TUNE_MASK != 0 {
+                    println!("  wrong hash for nonce {}, skipping", encoding.format(result.nonce));
+                    miner.stop_mining();
+                    return None;
+                }
+            },
+            MinoerEvent::Scanned(_, range) => dispatches += range.end - range.start,
+            MinoerEvent::Exhausted(_, thread) => {
+                miner.refill(thread);
+            },
+        }
+    }
+
+    let elapsed = started.elapsed();
+    miner.stop_mining();
+    // the memory clamp might have run fewer dispatches than the candidate asked for
+    let profile = miner.profile();
+
+    // 1 in 65536 hashes passes the mask, a dispatch without any means the kernel did nothing
+    if checked == 0 {
+        println!("  no finds at all, skipping");
+        return None;
+    }
+
+    let hashrate = (dispatches as f64 * profile.invocations as f64 / elapsed.as_secs_f64()) as u64;
+    println!("  ~{}h/s, {} finds checked", hashrate, checked);
+
+    return Some(Profile {
+        hashrate,
+        ..profile
+    });
+}
@@ -1,5 +1,9 @@
 
 use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
 use vulkano::device::Queue;
 use vulkano::device::Device;
@@ -12,8 +16,11 @@ use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::sync::GpuFuture;
 
-use super::miner::DISPATCH_SIZE;
+use crate::modules::nonce::NonceEncoding;
 use super::MinoeringResult;
+use super::MinoerJob;
+use super::INPUT_SIZE;
+use super::OUTPUT_WORDS;
 
 pub struct Worker {
     queue: Arc<Queue>,
@@ -23,8 +30,20 @@ pub struct Worker {
     command_buffers: Vec<Arc<PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>>>,
     futures: Vec<FenceSignalFuture<CommandBufferExecFuture<NowFuture>>>,
     max_dispatches: usize,
+    invocations: u32,
+    /** dispatches per round, moves around to keep each round close to batch_target
+     */
+    batch_size: usize,
+    batch_target: Duration,
+    batch_started: Instant,
     dispatch_index: usize,
     nonces: Vec<u64>,
+    encoding: NonceEncoding,
+    /** generation of the job being mined and the one the miner is on now
+     */
+    job_generation: u64,
+    generation: Arc<AtomicU64>,
+    mask: u32,
     minoers_mined: usize,
 }
 
@@ -36,6 +55,9 @@ impl Worker{
         output_staging_buffers: Vec<Subbuffer<[u32]>>,
         command_buffers: Vec<Arc<PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>>>,
         dispatch_amount: usize,
+        invocations: u32,
+        batch_target: Duration,
+        generation: Arc<AtomicU64>,
     ) -> Worker {
         Worker {
             device,
@@ -45,16 +67,27 @@ impl Worker{
             command_buffers,
             futures: Vec::with_capacity(dispatch_amount),
             max_dispatches: dispatch_amount,
+            invocations,
+            batch_size: dispatch_amount.min(8),
+            batch_target,
+            batch_started: Instant::now(),
             dispatch_index: 0,
             nonces: Vec::with_capacity(dispatch_amount),
+            encoding: NonceEncoding::default(),
+            job_generation: 0,
+            generation,
+            mask: 0,
             minoers_mined: 0,
         }
     }
 
-    pub fn submit(&mut self, data: [u32; 10], nonce: u64) {
+    pub fn submit(&mut self, data: [u32; INPUT_SIZE], nonce: u64) {
         if self.dispatch_index >= self.max_dispatches {
             panic!("bruh");
         }
+        if self.dispatch_index == 0 {
+            self.batch_started = Instant::now();
+        }
 
         self.input_staging_buffers[self.dispatch_index].write().unwrap().copy_from_slice(&data);
         // Let's execute this command buffer now.
@@ -76,8 +109,13 @@ impl Worker{
 
     pub fn get_results(&mut self) -> Vec<MinoeringResult> {
         let mut output = Vec::new();
-        for index in 0..self.max_dispatches {
+        for index in 0..self.futures.len() {
             self.futures[index].wait(None).unwrap();
+
+            // job got cancelled, the buffers still have to drain but theres no point reading them
+            if self.stale() {
+                continue;
+            }
             // let end = Instant::now();
             // println!("Elapsed: {}ms", end.duration_since(start).as_millis());
         
@@ -86,29 +124,55 @@ impl Worker{
             // GPU.
             let data_buffer_content = self.output_staging_buffers[index].read().unwrap();
     
-            for x in 0..(DISPATCH_SIZE*64 / 6 - 1) {
-                if data_buffer_content[(x * 6) as usize] == 0x000000 {
-                    // if data_buffer_content[(x * 6 + 1) as usize] == 0x000000 {
-                    let mut hashes = Vec::new();
-                    hashes.push(data_buffer_content[(x * 6) as usize]);
-                    hashes.push(data_buffer_content[(x * 6 + 1) as usize]);
-                    hashes.push(data_buffer_content[(x * 6 + 2) as usize]);
-                    hashes.push(data_buffer_content[(x * 6 + 3) as usize]);
-                    hashes.push(data_buffer_content[(x * 6 + 4) as usize]);
-    
+            for x in 0..self.invocations as usize {
+                let offset = x * OUTPUT_WORDS;
+                if data_buffer_content[offset] & self.mask == 0 {
+                    let hashes = data_buffer_content[offset..offset + 5].to_vec();
+
+                    // the kernel writes the low digits of the nonce from its invocation id,
+                    // and the submitted nonce has those digits zeroed in every encoding
+                    let nonce = data_buffer_content[offset + 5] as u64 + self.nonces[index];
+
                     output.push(
-                        MinoeringResult::new(data_buffer_content[(x * 6 + 5) as usize] as u64 + self.nonces[index], hashes, self.minoers_mined)
+                        MinoeringResult::new(self.job_generation, nonce, self.encoding, hashes, self.minoers_mined)
                     );
-                    // }
                 }
             }
         }
+        // a chunk ending early gives a short round, scale by how many dispatches actually ran
+        if !self.futures.is_empty() && !self.stale() {
+            self.adjust_batch(self.batch_started.elapsed(), self.futures.len());
+        }
+
         self.dispatch_index = 0;
         self.futures.clear();
         self.nonces.clear();
         return output;
     }
 
+    pub fn is_full(&self) -> bool {
+        self.dispatch_index >= self.batch_size
+    }
+
+    fn adjust_batch(&mut self, elapsed: Duration, dispatches: usize) {
+        let per_dispatch = elapsed.as_secs_f64() / dispatches as f64;
+        let ideal = self.batch_target.as_secs_f64() / per_dispatch;
+
+        // only go halfway there so one slow round doesnt throw it around
+        let next = (self.batch_size as f64 + ideal) / 2.0;
+        self.batch_size = (next.round() as usize).clamp(1, self.max_dispatches);
+    }
+
+    pub fn set_job(&mut self, job: &MinoerJob) {
+        self.encoding = job.encoding;
+        self.job_generation = job.generation;
+        self.mask = job.mask;
+    }
+
+    pub fn stale(&self) -> bool {
+        self.generation.load(Ordering::Relaxed) != self.job_generation
+    }
+
     pub fn reset(&mut self) {
         self.minoers_mined = 0;
     }
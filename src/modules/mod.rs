@@ -1,5 +1,12 @@
 pub mod mining;
+pub mod fakeserver;
 pub mod helpers;
 pub mod jason;
+pub mod ledger;
 pub mod network;
+pub mod nonce;
+pub mod order;
+pub mod outbox;
+pub mod progress;
+pub mod session;
 pub mod spmc;
\ No newline at end of file
@@ -0,0 +1,159 @@
+// the servers we can talk to, calls go to the best one thats up and come back to it once it recovers
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+
+use crate::jason::ConfigFile;
+use super::retry::NetError;
+
+#[derive(Default)]
+struct Health {
+    /** in a row, a success resets it
+     */
+    failures: u32,
+    down: bool,
+    calls: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+struct Endpoint {
+    url: String,
+    priority: u32,
+    health: Mutex<Health>,
+}
+
+/** shared by every account and threadid, a server thats down is down for all of them
+ */
+pub struct Endpoints {
+    client: Client,
+    /** best first
+     */
+    list: Vec<Endpoint>,
+    failover_after: u32,
+    /** so a switch only gets printed once
+     */
+    active: Mutex<usize>,
+}
+
+impl Endpoints {
+    pub fn new(client: Client, config: &ConfigFile) -> Arc<Endpoints> {
+        let mut list: Vec<Endpoint> = config.servers.iter()
+            .map(|server| Endpoint {
+                url: server.url.trim_end_matches('/').to_string(),
+                priority: server.priority,
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+        list.sort_by_key(|e| e.priority);
+
+        let endpoints = Arc::new(Endpoints {
+            client,
+            list,
+            failover_after: config.failover_after.max(1),
+            active: Mutex::new(0),
+        });
+
+        if endpoints.list.len() > 1 && config.health_check_s > 0 {
+            let checker = endpoints.clone();
+            let interval = Duration::from_secs(config.health_check_s);
+            thread::spawn(move || {
+                loop {
+                    thread::sleep(interval);
+                    checker.check();
+                }
+            });
+        }
+
+        return endpoints;
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /** the best one thats up, or the best one if theyre all down. `index` goes back into `report`
+     */
+    pub fn pick(&self) -> (usize, String) {
+        let index = self.list.iter()
+            .position(|e| !e.health.lock().unwrap().down)
+            .unwrap_or(0);
+
+        let mut active = self.active.lock().unwrap();
+        if *active != index {
+            println!("switching from {} to {}", self.list[*active].url, self.list[index].url);
+            *active = index;
+        }
+
+        return (index, self.list[index].url.clone());
+    }
+
+    /** only the failures that say the server isnt there count, a bad password is the same everywhere
+     */
+    pub fn report<T>(&self, index: usize, result: &Result<T, NetError>) {
+        let endpoint = &self.list[index];
+        let mut health = endpoint.health.lock().unwrap();
+        health.calls += 1;
+
+        match result {
+            Err(err) if err.retryable() => {
+                health.failures += 1;
+                health.failed += 1;
+                health.last_error = Some(err.to_string());
+
+                if health.failures >= self.failover_after && !health.down && self.list.len() > 1 {
+                    println!("{} failed {} times in a row, failing over", endpoint.url, health.failures);
+                    health.down = true;
+                }
+            },
+            _ => {
+                health.failures = 0;
+                health.down = false;
+            },
+        }
+    }
+
+    /** anything that answers at all is up again, a 404 on the root still means its there
+     */
+    fn check(&self) {
+        for endpoint in &self.list {
+            if !endpoint.health.lock().unwrap().down {
+                continue;
+            }
+
+            let up = match self.client.get(&endpoint.url).send() {
+                Ok(res) => !res.status().is_server_error(),
+                Err(_) => false,
+            };
+            if up {
+                println!("{} answers again", endpoint.url);
+                let mut health = endpoint.health.lock().unwrap();
+                health.down = false;
+                health.failures = 0;
+            }
+        }
+    }
+
+    /** one line for the stats
+     */
+    pub fn health(&self) -> String {
+        let active = *self.active.lock().unwrap();
+
+        self.list.iter()
+            .enumerate()
+            .map(|(i, endpoint)| {
+                let health = endpoint.health.lock().unwrap();
+                let state = if health.down { "down" } else if i == active { "active" } else { "up" };
+                let error = match (&health.last_error, health.down) {
+                    (Some(err), true) => format!(", last error: {}", err),
+                    _ => String::new(),
+                };
+                format!("{} [{}] {}, {}/{} calls failed{}", endpoint.url, endpoint.priority, state, health.failed, health.calls, error)
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use crate::jason::Account;
+use crate::jason::ConfigFile;
+use crate::modules::nonce::NonceEncoding;
+use super::failover::Endpoints;
+use super::ratelimit::Limiter;
+use super::retry::retry;
+use super::retry::retry_when;
+use super::retry::NetError;
+use super::retry::RetryPolicy;
+use super::PoolClient;
+use super::SubmitOutcome;
+
+#[derive(Serialize, Debug)]
+pub struct UserHashGetRequest<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub threadid: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserHashSetRequest<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub threadid: u32,
+    /** a number or a string depending on the nonce encoding
+     */
+    pub nonce: serde_json::Value,
+}
+
+/**
+ * the `status` field of both responses
+ * the api doesnt document these so a few spellings are accepted, anything else is kept as is
+ */
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "String")]
+pub enum Status {
+    Success,
+    BadNonce,
+    StaleHash,
+    BadAuth,
+    Other(String),
+}
+
+impl From<String> for Status {
+    fn from(status: String) -> Status {
+        match status.to_lowercase().as_str() {
+            "success" | "ok" => Status::Success,
+            "invalid nonce" | "bad nonce" | "wrong nonce" => Status::BadNonce,
+            "invalid hash" | "old hash" | "stale hash" | "hash expired" => Status::StaleHash,
+            "invalid credentials" | "bad credentials" | "invalid login" | "unauthorized" => Status::BadAuth,
+            _ => Status::Other(status),
+        }
+    }
+}
+
+/** only the status is there when it didnt work out
+ */
+#[derive(Deserialize, Debug)]
+pub struct UserHashSetResponse {
+    #[serde(default)]
+    pub balance: u32,
+    #[serde(default)]
+    pub reward: u32,
+    #[serde(default)]
+    pub newhash: String,
+    pub status: Status,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserHashGetResponse {
+    #[serde(default)]
+    pub hash: String,
+    pub status: Status,
+}
+
+/** gabserver.eu, or anything else speaking its api at one of the `servers`
+ */
+pub struct GabServer {
+    endpoints: Arc<Endpoints>,
+    /** every attempt at a submit takes a spot, retries hit the server just as much
+     */
+    limiter: Arc<Limiter>,
+    hash_get_path: String,
+    hash_set_path: String,
+    username: String,
+    password: String,
+    thread_id: u32,
+    hash_get_retry: RetryPolicy,
+    submit_retry: RetryPolicy,
+}
+
+impl GabServer {
+    pub fn new(endpoints: Arc<Endpoints>, limiter: Arc<Limiter>, config: &ConfigFile, account: &Account, thread_id: u32) -> GabServer {
+        GabServer {
+            endpoints,
+            limiter,
+            hash_get_path: config.hash_get_path.clone(),
+            hash_set_path: config.hash_set_path.clone(),
+            username: account.username.clone(),
+            password: account.password.clone(),
+            thread_id,
+            hash_get_retry: config.hash_get_retry,
+            submit_retry: config.submit_retry,
+        }
+    }
+
+    /** one try at whichever server is best right now, so retries fail over on their own
+     */
+    fn on_endpoint<T>(&self, path: &str, call: impl FnOnce(&str) -> Result<T, NetError>) -> Result<T, NetError> {
+        let (index, base) = self.endpoints.pick();
+        let result = call(&(base + path));
+        self.endpoints.report(index, &result);
+
+        return result;
+    }
+
+    /** one userhashset at `url`, the caller decides what happens if it fails
+     */
+    fn submit_once(&self, url: &str, body: &UserHashSetRequest) -> Result<SubmitOutcome, NetError> {
+        let res = self.endpoints.client().post(url)
+            .json(body)
+            .send()
+            .map_err(NetError::classify)?;
+
+        // the server being down isnt the nonce's fault, anything else gets a look at the body
+        let status = res.status();
+        if status.is_server_error() || status.as_u16() == 408 || status.as_u16() == 429 {
+            return Err(NetError::Status(status.as_u16()));
+        }
+
+        // RAAH I LOVE RUSSY
+        let res = match res.json::<UserHashSetResponse>() {
+            Ok(r) => r,
+            Err(e) if e.is_decode() && is_auth_error(status) => return Ok(SubmitOutcome::BadAuth),
+            Err(e) if e.is_decode() => return Ok(SubmitOutcome::Unknown(String::from("unreadable response"))),
+            Err(e) => return Err(NetError::classify(e)),
+        };
+
+        // the status strings are guesses, a new hash and a reward mean it got paid whatever the status says
+        if !res.newhash.is_empty() && res.reward > 0 {
+            return Ok(SubmitOutcome::Accepted {
+                reward: res.reward,
+                balance: res.balance,
+                newhash: res.newhash,
+            });
+        }
+
+        return Ok(match res.status {
+            Status::Success => SubmitOutcome::Accepted {
+                reward: res.reward,
+                balance: res.balance,
+                newhash: res.newhash,
+            },
+            Status::BadNonce => SubmitOutcome::BadNonce,
+            Status::StaleHash => SubmitOutcome::StaleHash,
+            Status::BadAuth => SubmitOutcome::BadAuth,
+            Status::Other(status) => SubmitOutcome::Unknown(status),
+        });
+    }
+}
+
+impl PoolClient for GabServer {
+    fn get_hash(&self) -> Result<String, NetError> {
+        let body = UserHashGetRequest {
+            username: &self.username,
+            password: &self.password,
+            threadid: self.thread_id,
+        };
+
+        retry(&self.hash_get_retry, "hash get", || self.on_endpoint(&self.hash_get_path, |url| {
+            let res = self.endpoints.client().post(url)
+                .json(&body)
+                .send()
+                .map_err(NetError::classify)?;
+
+            // a 4xx can still carry a status worth reading, like bad credentials
+            let code = res.status();
+            let res = match res.json::<UserHashGetResponse>() {
+                Ok(r) => r,
+                Err(_) if is_auth_error(code) => return Err(NetError::BadAuth),
+                Err(_) if !code.is_success() => return Err(NetError::Status(code.as_u16())),
+                Err(e) => return Err(NetError::classify(e)),
+            };
+
+            match res.status {
+                Status::Success => Ok(res.hash),
+                Status::BadAuth => Err(NetError::BadAuth),
+                status => Err(NetError::Refused(format!("{:?}", status))),
+            }
+        }))
+    }
+
+    fn submit(&self, nonce: u64, encoding: NonceEncoding) -> Result<SubmitOutcome, NetError> {
+        let body = UserHashSetRequest {
+            username: &self.username,
+            password: &self.password,
+            threadid: self.thread_id,
+            nonce: encoding.to_json(nonce),
+        };
+
+        // a set isnt safe to repeat, if it went through and only the answer got lost the retry just looks stale.
+        // so only what never reached the server gets retried, and on the same server, anything else stays in the outbox
+        let (index, base) = self.endpoints.pick();
+        let url = base + &self.hash_set_path;
+
+        retry_when(&self.submit_retry, "submit", NetError::never_arrived, || {
+            self.limiter.wait();
+            let result = self.submit_once(&url, &body);
+            self.endpoints.report(index, &result);
+            result
+        })
+    }
+
+    fn health(&self) -> Option<String> {
+        Some(self.endpoints.health())
+    }
+}
+
+/** a 401 or 403 without a body worth reading
+ */
+fn is_auth_error(code: StatusCode) -> bool {
+    code == StatusCode::UNAUTHORIZED || code == StatusCode::FORBIDDEN
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::modules::network::build_client;
+
+    #[test]
+    fn requests_on_the_wire() {
+        let get = UserHashGetRequest { username: "gab", password: "p\"a\\ss", threadid: 3 };
+        assert_eq!(serde_json::to_string(&get).unwrap(), r#"{"username":"gab","password":"p\"a\\ss","threadid":3}"#);
+
+        let set = UserHashSetRequest { username: "gab", password: "x", threadid: 0, nonce: NonceEncoding::Decimal.to_json(10_000_000_000_000_012_345) };
+        assert_eq!(serde_json::to_string(&set).unwrap(), r#"{"username":"gab","password":"x","threadid":0,"nonce":10000000000000012345}"#);
+
+        let set = UserHashSetRequest { username: "gab", password: "x", threadid: 1, nonce: NonceEncoding::Hex.to_json(255) };
+        assert_eq!(serde_json::to_string(&set).unwrap(), r#"{"username":"gab","password":"x","threadid":1,"nonce":"00000000000000ff"}"#);
+    }
+
+    #[test]
+    fn responses_off_the_wire() {
+        let res: UserHashGetResponse = serde_json::from_str(r#"{"hash":"8deda67f452dc5de673a01fad1580ca4429bc166a4e3dc5d3911535616327e32","status":"success"}"#).unwrap();
+        assert_eq!(res.status, Status::Success);
+        assert_eq!(res.hash.len(), 64);
+
+        let res: UserHashGetResponse = serde_json::from_str(r#"{"status":"invalid credentials"}"#).unwrap();
+        assert_eq!(res.status, Status::BadAuth);
+        assert_eq!(res.hash, "");
+
+        let res: UserHashSetResponse = serde_json::from_str(r#"{"balance":120,"reward":10,"newhash":"abc","status":"success"}"#).unwrap();
+        assert_eq!((res.balance, res.reward, res.newhash.as_str(), res.status), (120, 10, "abc", Status::Success));
+
+        let res: UserHashSetResponse = serde_json::from_str(r#"{"status":"invalid nonce"}"#).unwrap();
+        assert_eq!((res.balance, res.reward, res.status), (0, 0, Status::BadNonce));
+    }
+
+    #[test]
+    fn status_spellings() {
+        for (text, status) in [
+            ("success", Status::Success),
+            ("OK", Status::Success),
+            ("Invalid Nonce", Status::BadNonce),
+            ("wrong nonce", Status::BadNonce),
+            ("hash expired", Status::StaleHash),
+            ("old hash", Status::StaleHash),
+            ("unauthorized", Status::BadAuth),
+            ("invalid login", Status::BadAuth),
+            ("rate limited", Status::Other(String::from("rate limited"))),
+        ] {
+            assert_eq!(Status::from(String::from(text)), status, "{}", text);
+        }
+    }
+
+    /** answers `responses` in order, one connection each, and hands back how many requests came in
+     */
+    fn canned(responses: Vec<String>) -> (String, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let mut served = 0;
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 4096];
+                let mut got = Vec::new();
+                // headers and the small json body, the client sends both before waiting
+                while !got.ends_with(b"}") {
+                    match stream.read(&mut buf) {
+                        Ok(n) if n > 0 => got.extend_from_slice(&buf[..n]),
+                        _ => break,
+                    }
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                served += 1;
+            }
+            return served;
+        });
+
+        return (url, server);
+    }
+
+    fn reply(status: &str, body: &str) -> String {
+        format!("HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", status, body.len(), body)
+    }
+
+    fn pool(url: &str) -> GabServer {
+        return pool_on(&[url]);
+    }
+
+    /** best first, one failure is enough to fail over
+     */
+    fn pool_on(urls: &[&str]) -> GabServer {
+        let servers: Vec<_> = urls.iter().enumerate().map(|(i, url)| json!({ "url": url, "priority": i })).collect();
+        let config: ConfigFile = serde_json::from_value(json!({
+            "username": "a",
+            "password": "p",
+            "lobotomize": true,
+            "servers": servers,
+            "failover_after": 1,
+            "health_check_s": 0,
+            "hash_get_retry": { "attempts": 3, "base_delay_ms": 1, "max_delay_ms": 1 },
+            "submit_retry": { "attempts": 3, "base_delay_ms": 1, "max_delay_ms": 1 },
+        })).unwrap();
+        let endpoints = Endpoints::new(build_client(&config), &config);
+        let account = Account { username: String::from("a"), password: String::from("p"), weight: 1 };
+
+        return GabServer::new(endpoints, Limiter::new("a", None), &config, &account, 0);
+    }
+
+    fn submit(responses: Vec<String>) -> (Result<SubmitOutcome, NetError>, usize) {
+        let (url, server) = canned(responses);
+        let outcome = pool(&url).submit(10_000_000_000_000_000_000, NonceEncoding::Decimal);
+
+        return (outcome, server.join().unwrap());
+    }
+
+    #[test]
+    fn paid_whatever_the_status_says() {
+        let (outcome, _) = submit(vec![reply("200 OK", r#"{"balance":20,"reward":10,"newhash":"abc","status":"yay"}"#)]);
+
+        assert!(matches!(outcome, Ok(SubmitOutcome::Accepted { reward: 10, balance: 20, .. })), "{:?}", outcome);
+    }
+
+    #[test]
+    fn bare_401_is_bad_auth() {
+        let (outcome, _) = submit(vec![reply("401 Unauthorized", "")]);
+        assert!(matches!(outcome, Ok(SubmitOutcome::BadAuth)), "{:?}", outcome);
+
+        let (outcome, _) = submit(vec![reply("403 Forbidden", "")]);
+        assert!(matches!(outcome, Ok(SubmitOutcome::BadAuth)), "{:?}", outcome);
+    }
+
+    #[test]
+    fn maybe_delivered_submit_isnt_retried() {
+        let (outcome, served) = submit(vec![reply("500 Internal Server Error", "")]);
+
+        assert!(matches!(outcome, Err(NetError::Status(500))), "{:?}", outcome);
+        assert_eq!(served, 1);
+    }
+
+    #[test]
+    fn throttled_submit_is_retried() {
+        let (outcome, served) = submit(vec![
+            reply("429 Too Many Requests", ""),
+            reply("200 OK", r#"{"status":"invalid nonce"}"#),
+        ]);
+
+        assert!(matches!(outcome, Ok(SubmitOutcome::BadNonce)), "{:?}", outcome);
+        assert_eq!(served, 2);
+    }
+
+    #[test]
+    fn down_server_fails_over_to_the_next() {
+        let (primary, primary_served) = canned(vec![reply("503 Service Unavailable", "")]);
+        let (backup, backup_served) = canned(vec![reply("200 OK", r#"{"hash":"abc","status":"success"}"#)]);
+        let pool = pool_on(&[&primary, &backup]);
+
+        let hash = pool.get_hash();
+        assert!(matches!(hash, Ok(ref h) if h == "abc"), "{:?}", hash);
+        assert_eq!((primary_served.join().unwrap(), backup_served.join().unwrap()), (1, 1));
+        assert_eq!(pool.endpoints.pick().1, backup);
+    }
+
+    #[test]
+    fn refusal_stays_on_the_same_server() {
+        let (primary, primary_served) = canned(vec![
+            reply("200 OK", r#"{"status":"invalid credentials"}"#),
+            reply("400 Bad Request", ""),
+        ]);
+        let (backup, backup_served) = canned(vec![]);
+        let pool = pool_on(&[&primary, &backup]);
+
+        assert!(matches!(pool.get_hash(), Err(NetError::BadAuth)));
+        assert!(matches!(pool.get_hash(), Err(NetError::Status(400))));
+        assert_eq!((primary_served.join().unwrap(), backup_served.join().unwrap()), (2, 0));
+        assert_eq!(pool.endpoints.pick().1, primary);
+    }
+}
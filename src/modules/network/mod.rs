@@ -0,0 +1,136 @@
+// anything we can get challenges from and hand nonces to
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::NoProxy;
+use reqwest::Proxy;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::jason::ConfigFile;
+use crate::modules::nonce::NonceEncoding;
+
+pub mod failover;
+pub mod gabserver;
+pub mod ratelimit;
+pub mod retry;
+pub mod submitter;
+pub mod tls;
+pub mod watcher;
+pub mod workclient;
+pub mod workproxy;
+
+use retry::NetError;
+
+/** how long to sit out after a server call ran out of retries, or couldnt be reached at startup
+ */
+pub const OUTAGE_POLL: Duration = Duration::from_secs(30);
+
+/** what the server made of a submitted nonce
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SubmitOutcome {
+    Accepted {
+        reward: u32,
+        balance: u32,
+        /** challenge to mine next
+         */
+        newhash: String,
+    },
+    /** doesnt hash to what the server wants
+     */
+    BadNonce,
+    /** the challenge isnt current anymore
+     */
+    StaleHash,
+    /** username or password is wrong
+     */
+    BadAuth,
+    /** status we dont know, or a response we couldnt read
+     */
+    Unknown(String),
+}
+
+/**
+ * a proof of work site, the mining loop only talks to the server through this
+ * so another site or a local test server is just another impl
+ */
+pub trait PoolClient {
+    /** the challenge we should be mining right now, errors only once retrying gave up
+     */
+    fn get_hash(&self) -> Result<String, NetError>;
+
+    fn submit(&self, nonce: u64, encoding: NonceEncoding) -> Result<SubmitOutcome, NetError>;
+
+    /** how the servers behind it are doing, for the stats
+     */
+    fn health(&self) -> Option<String> {
+        None
+    }
+}
+
+/** http client with the timeouts, user agent, proxy and tls setup from the config
+ */
+pub fn build_client(config: &ConfigFile) -> Client {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .timeout(Duration::from_millis(config.request_timeout_ms))
+        .user_agent(config.user_agent.as_str());
+
+    if let Some(url) = &config.proxy {
+        let proxy = Proxy::all(url.as_str())
+            .unwrap_or_else(|err| panic!("proxy {} doesnt look right: {}", url, err))
+            .no_proxy(NoProxy::from_string(&config.no_proxy.join(",")));
+        builder = builder.proxy(proxy);
+    }
+
+    if !config.pins.is_empty() {
+        return builder
+            .use_preconfigured_tls(tls::pinned_config(config))
+            .build()
+            .expect("couldnt build the http client");
+    }
+
+    for cert in tls::root_certs(&config.ca_certs) {
+        builder = builder.add_root_certificate(cert);
+    }
+    if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+        builder = builder.identity(tls::identity(cert, key));
+    }
+    if let Some(version) = config.tls_min_version {
+        builder = builder.min_tls_version(version.into());
+    }
+
+    builder.build().expect("couldnt build the http client")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn config(tls_min_version: &str, pins: &[&str]) -> ConfigFile {
+        serde_json::from_value(json!({
+            "username": "a",
+            "password": "p",
+            "lobotomize": true,
+            "servers": [{ "url": "https://127.0.0.1:1" }],
+            "tls_min_version": tls_min_version,
+            "pins": pins,
+        })).unwrap()
+    }
+
+    #[test]
+    fn every_tls_min_version_builds() {
+        for version in ["1.0", "1.1", "1.2"] {
+            build_client(&config(version, &[]));
+        }
+
+        // read_config only lets 1.3 through with pins
+        let pin = "0".repeat(64);
+        for version in ["1.0", "1.1", "1.2", "1.3"] {
+            build_client(&config(version, &[&pin]));
+        }
+    }
+}
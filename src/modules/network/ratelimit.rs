@@ -0,0 +1,150 @@
+// caps how fast an account submits, a runaway rig hammering the server is how accounts get banned
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::modules::nonce::NonceEncoding;
+use super::retry::NetError;
+use super::PoolClient;
+use super::SubmitOutcome;
+
+/** at most `count` submissions in any `per_s` seconds
+ */
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct SubmitLimit {
+    pub count: u32,
+    pub per_s: u64,
+}
+
+pub struct Limiter {
+    name: String,
+    limit: Option<SubmitLimit>,
+    /** when the submissions still inside the window went out, oldest first
+     */
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl Limiter {
+    pub fn new(name: &str, limit: Option<SubmitLimit>) -> Arc<Limiter> {
+        Arc::new(Limiter {
+            name: name.to_string(),
+            limit,
+            sent: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /** one per username, every threadid of an account shares its limit
+     */
+    pub fn per_account<'a>(usernames: impl Iterator<Item = &'a str>, limit: Option<SubmitLimit>) -> HashMap<String, Arc<Limiter>> {
+        usernames
+            .map(|username| (username.to_string(), Limiter::new(username, limit)))
+            .collect()
+    }
+
+    /** blocks until theres room in the window, then takes a spot
+     */
+    pub fn wait(&self) {
+        let limit = match self.limit {
+            Some(l) if l.count > 0 => l,
+            _ => return,
+        };
+        let window = Duration::from_secs(limit.per_s);
+
+        loop {
+            let mut sent = self.sent.lock().unwrap();
+            while sent.front().is_some_and(|t| t.elapsed() >= window) {
+                sent.pop_front();
+            }
+            if sent.len() < limit.count as usize {
+                sent.push_back(Instant::now());
+                return;
+            }
+
+            let wait = window - sent.front().unwrap().elapsed();
+            drop(sent);
+            println!("{}: {} submissions in the last {}s already, holding the next one for {}ms", self.name, limit.count, limit.per_s, wait.as_millis());
+            thread::sleep(wait);
+        }
+    }
+}
+
+/**
+ * a pool whose submits wait their turn, the finds pile up in the outbox meanwhile.
+ * for the work proxy's jobs, GabServer waits on every attempt itself
+ */
+pub struct Limited {
+    pool: Arc<dyn PoolClient + Send + Sync>,
+    limiter: Arc<Limiter>,
+}
+
+impl Limited {
+    pub fn new(pool: Arc<dyn PoolClient + Send + Sync>, limiter: Arc<Limiter>) -> Limited {
+        Limited {
+            pool,
+            limiter,
+        }
+    }
+}
+
+impl PoolClient for Limited {
+    fn get_hash(&self) -> Result<String, NetError> {
+        self.pool.get_hash()
+    }
+
+    fn submit(&self, nonce: u64, encoding: NonceEncoding) -> Result<SubmitOutcome, NetError> {
+        self.limiter.wait();
+        self.pool.submit(nonce, encoding)
+    }
+
+    fn health(&self) -> Option<String> {
+        self.pool.health()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_what_doesnt_fit_in_the_window() {
+        let limiter = Limiter::new("a", Some(SubmitLimit { count: 2, per_s: 1 }));
+        let started = Instant::now();
+
+        limiter.wait();
+        limiter.wait();
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        limiter.wait();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn no_limit_never_waits() {
+        let started = Instant::now();
+        for limit in [None, Some(SubmitLimit { count: 0, per_s: 60 })] {
+            let limiter = Limiter::new("a", limit);
+            for _ in 0..100 {
+                limiter.wait();
+            }
+        }
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn accounts_dont_share_a_limit() {
+        let limiters = Limiter::per_account(["a", "b", "a"].into_iter(), Some(SubmitLimit { count: 1, per_s: 60 }));
+        let started = Instant::now();
+
+        limiters["a"].wait();
+        limiters["b"].wait();
+        assert_eq!(limiters.len(), 2);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+}
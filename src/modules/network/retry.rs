@@ -0,0 +1,158 @@
+// server calls fail for all kinds of dumb reasons, try again before giving up
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::modules::helpers;
+use crate::modules::network::tls::PIN_MISMATCH;
+
+#[derive(Debug)]
+pub enum NetError {
+    Dns(String),
+    Connect(String),
+    Timeout(String),
+    /** server answered with a non success status
+     */
+    Status(u16),
+    BadJson(String),
+    /** credentials got refused
+     */
+    BadAuth,
+    /** server answered but said no
+     */
+    Refused(String),
+    /** the server's certificate isnt one of the pinned ones, credentials never went out
+     */
+    PinMismatch(String),
+    Other(String),
+}
+
+impl NetError {
+    pub fn classify(err: reqwest::Error) -> NetError {
+        // a connect timeout never got the request out, thats a connect error not a timeout
+        if err.is_timeout() && !err.is_connect() {
+            return NetError::Timeout(err.to_string());
+        }
+        if err.is_decode() {
+            return NetError::BadJson(err.to_string());
+        }
+        if let Some(status) = err.status() {
+            return NetError::Status(status.as_u16());
+        }
+        // the verifier's error only shows up as text in an io error further down
+        let mut source = err.source();
+        while let Some(s) = source {
+            let text = s.to_string();
+            if text.contains(PIN_MISMATCH) {
+                return NetError::PinMismatch(text);
+            }
+            source = s.source();
+        }
+        if err.is_connect() {
+            // reqwest doesnt tell dns apart, hyper's resolver error is somewhere down the source chain
+            let mut source = err.source();
+            while let Some(s) = source {
+                let text = s.to_string().to_lowercase();
+                if text.contains("dns") || text.contains("lookup") || text.contains("resolve") {
+                    return NetError::Dns(err.to_string());
+                }
+                source = s.source();
+            }
+            return NetError::Connect(err.to_string());
+        }
+
+        return NetError::Other(err.to_string());
+    }
+
+    /** a bad json or a 4xx wont fix itself by asking again
+     */
+    pub fn retryable(&self) -> bool {
+        match self {
+            NetError::Status(code) => *code >= 500 || *code == 408 || *code == 429,
+            NetError::BadJson(_) | NetError::BadAuth | NetError::Refused(_) | NetError::PinMismatch(_) => false,
+            _ => true,
+        }
+    }
+
+    /**
+     * the request surely never got to the server, so sending it again cant do anything twice.
+     * a timeout or a dropped connection could be after the server already did it
+     */
+    pub fn never_arrived(&self) -> bool {
+        match self {
+            NetError::Dns(_) | NetError::Connect(_) => true,
+            NetError::Status(code) => *code == 408 || *code == 429,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::Dns(e) => write!(f, "dns lookup failed: {}", e),
+            NetError::Connect(e) => write!(f, "couldnt connect: {}", e),
+            NetError::Timeout(e) => write!(f, "timed out: {}", e),
+            NetError::Status(code) => write!(f, "server said {}", code),
+            NetError::BadJson(e) => write!(f, "bad json: {}", e),
+            NetError::BadAuth => write!(f, "wrong username or password"),
+            NetError::Refused(status) => write!(f, "server refused: {}", status),
+            NetError::PinMismatch(e) => write!(f, "certificate pin mismatch, not sending anything: {}", e),
+            NetError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /** tries in total, including the first one
+     */
+    pub attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    /**
+     * full jitter: a random wait up to base * 2^attempt, capped at max,
+     * so a farm of rigs that lost the server together doesnt come back together
+     */
+    fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.base_delay_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.max_delay_ms);
+
+        return Duration::from_millis(helpers::random() % (ceiling + 1));
+    }
+}
+
+/** runs `call` until it works, fails with something not worth retrying, or runs out of attempts
+ */
+pub fn retry<T>(policy: &RetryPolicy, what: &str, call: impl FnMut() -> Result<T, NetError>) -> Result<T, NetError> {
+    return retry_when(policy, what, NetError::retryable, call);
+}
+
+/** same but `worth_it` decides which errors get another go
+ */
+pub fn retry_when<T>(policy: &RetryPolicy, what: &str, worth_it: impl Fn(&NetError) -> bool, mut call: impl FnMut() -> Result<T, NetError>) -> Result<T, NetError> {
+    let mut attempt = 0;
+
+    loop {
+        let err = match call() {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        if attempt >= policy.attempts || !worth_it(&err) {
+            return Err(err);
+        }
+
+        let delay = policy.delay(attempt - 1);
+        println!("{} failed ({}), retrying in {}ms", what, err, delay.as_millis());
+        thread::sleep(delay);
+    }
+}
@@ -0,0 +1,150 @@
+// submits finds on its own thread so the main loop never sits on an http round trip
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::modules::outbox::Find;
+use super::retry::NetError;
+use super::PoolClient;
+use super::SubmitOutcome;
+
+struct Submission {
+    find: Find,
+    found_at: Instant,
+    /** an earlier try might have got through, only send it again if the server is still on its challenge
+     */
+    recheck: bool,
+}
+
+pub struct SubmitReport {
+    pub find: Find,
+    /** None if it didnt go out again because the server moved on since a try that might have got through
+     */
+    pub outcome: Option<Result<SubmitOutcome, NetError>>,
+    /** just the request, retries included
+     */
+    pub round_trip: Duration,
+    /** from the gpu handing it over to the server answering
+     */
+    pub latency: Duration,
+    /** asked for here after a stale or unknown answer or for a recheck, so an outage doesnt hold up the mining loop
+     */
+    pub new_hash: Option<Result<String, NetError>>,
+}
+
+pub struct Submitter {
+    sender: Sender<Submission>,
+    reports: Receiver<SubmitReport>,
+}
+
+impl Submitter {
+    pub fn new<P: PoolClient + Send + Sync + ?Sized + 'static>(pool: Arc<P>) -> Submitter {
+        let (sender, receiver) = mpsc::channel::<Submission>();
+        let (report_sender, reports) = mpsc::channel::<SubmitReport>();
+
+        thread::spawn(move || {
+            for submission in receiver {
+                let started = Instant::now();
+                let find = &submission.find;
+
+                // the server moves on after every paid find, still being on this challenge means it wasnt paid
+                let (outcome, new_hash) = if submission.recheck {
+                    match pool.get_hash() {
+                        Ok(hash) if hash != find.challenge => (None, Some(Ok(hash))),
+                        Ok(_) => (Some(pool.submit(find.nonce, find.encoding)), None),
+                        Err(err) => (Some(Err(err)), None),
+                    }
+                } else {
+                    (Some(pool.submit(find.nonce, find.encoding)), None)
+                };
+                let round_trip = started.elapsed();
+                let latency = submission.found_at.elapsed();
+
+                let new_hash = match outcome {
+                    Some(Ok(SubmitOutcome::StaleHash)) | Some(Ok(SubmitOutcome::Unknown(_))) => Some(pool.get_hash()),
+                    _ => new_hash,
+                };
+
+                let report = SubmitReport {
+                    find: submission.find,
+                    outcome,
+                    round_trip,
+                    latency,
+                    new_hash,
+                };
+                if report_sender.send(report).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Submitter {
+            sender,
+            reports,
+        }
+    }
+
+    /** `found_at` is when the gpu came up with it, for the latency numbers
+     */
+    pub fn submit(&self, find: Find, found_at: Instant, recheck: bool) {
+        self.sender.send(Submission { find, found_at, recheck }).expect("submitter thread died");
+    }
+
+    pub fn try_report(&self) -> Option<SubmitReport> {
+        self.reports.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::modules::nonce::NonceEncoding;
+
+    struct Pool {
+        hash: &'static str,
+        submits: AtomicU32,
+    }
+
+    impl PoolClient for Pool {
+        fn get_hash(&self) -> Result<String, NetError> {
+            Ok(self.hash.to_string())
+        }
+
+        fn submit(&self, _: u64, _: NonceEncoding) -> Result<SubmitOutcome, NetError> {
+            self.submits.fetch_add(1, Ordering::Relaxed);
+            Ok(SubmitOutcome::BadNonce)
+        }
+    }
+
+    fn send(hash: &'static str, recheck: bool) -> (SubmitReport, u32) {
+        let pool = Arc::new(Pool { hash, submits: AtomicU32::new(0) });
+        let submitter = Submitter::new(pool.clone());
+        submitter.submit(Find::new("abc", 1, NonceEncoding::Hex, &[0; 5]), Instant::now(), recheck);
+
+        let report = submitter.reports.recv().unwrap();
+        return (report, pool.submits.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn maybe_sent_find_isnt_sent_again_once_the_server_moved_on() {
+        let (report, submits) = send("def", true);
+        assert!(report.outcome.is_none());
+        assert!(matches!(report.new_hash, Some(Ok(ref h)) if h == "def"));
+        assert_eq!(submits, 0);
+
+        let (report, submits) = send("abc", true);
+        assert!(matches!(report.outcome, Some(Ok(SubmitOutcome::BadNonce))));
+        assert_eq!(submits, 1);
+
+        let (report, submits) = send("def", false);
+        assert!(matches!(report.outcome, Some(Ok(SubmitOutcome::BadNonce))));
+        assert_eq!(submits, 1);
+    }
+}
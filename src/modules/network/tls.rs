@@ -0,0 +1,340 @@
+// certificates and tls settings for talking to the server through whatever is in between
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+use reqwest::tls;
+use reqwest::Certificate;
+use reqwest::Identity;
+use reqwest::Url;
+use ring::digest;
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::client::danger::ServerCertVerified;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::pki_types::UnixTime;
+use rustls::server::ParsedCertificate;
+use rustls::CertificateError;
+use rustls::ClientConfig;
+use rustls::DigitallySignedStruct;
+use rustls::OtherError;
+use rustls::RootCertStore;
+use rustls::SignatureScheme;
+use serde::Deserialize;
+
+use crate::jason::ConfigFile;
+
+/** shows up in the error text so NetError can tell a pin mismatch from any other failed handshake
+ */
+pub const PIN_MISMATCH: &str = "isnt pinned";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls10,
+    #[serde(rename = "1.1")]
+    Tls11,
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl From<TlsVersion> for tls::Version {
+    fn from(version: TlsVersion) -> tls::Version {
+        match version {
+            TlsVersion::Tls10 => tls::Version::TLS_1_0,
+            TlsVersion::Tls11 => tls::Version::TLS_1_1,
+            TlsVersion::Tls12 => tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => tls::Version::TLS_1_3,
+        }
+    }
+}
+
+/** every certificate in every file, a file can be a whole bundle
+ */
+pub fn root_certs(paths: &[String]) -> Vec<Certificate> {
+    let mut certs = Vec::new();
+
+    for path in paths {
+        let pem = fs::read(path).unwrap_or_else(|err| panic!("couldnt read ca cert {}: {}", path, err));
+        let bundle = Certificate::from_pem_bundle(&pem).unwrap_or_else(|err| panic!("{} isnt a pem certificate: {}", path, err));
+        if bundle.is_empty() {
+            panic!("no certificates in {}", path);
+        }
+        certs.extend(bundle);
+    }
+
+    return certs;
+}
+
+pub fn identity(cert_path: &str, key_path: &str) -> Identity {
+    let cert = fs::read(cert_path).unwrap_or_else(|err| panic!("couldnt read client cert {}: {}", cert_path, err));
+    let key = fs::read(key_path).unwrap_or_else(|err| panic!("couldnt read client key {}: {}", key_path, err));
+
+    return Identity::from_pkcs8_pem(&cert, &key)
+        .unwrap_or_else(|err| panic!("{} and {} dont make a client identity, the key has to be pkcs8: {}", cert_path, key_path, err));
+}
+
+/** hex sha256 of the der SubjectPublicKeyInfo, what goes in `pins`
+ */
+pub fn spki_pin(cert: &CertificateDer) -> Option<String> {
+    let parsed = ParsedCertificate::try_from(cert).ok()?;
+    let spki = parsed.subject_public_key_info();
+
+    return Some(hex::encode(digest::digest(&digest::SHA256, spki.as_ref())));
+}
+
+struct PinMismatch(String);
+
+// rustls only shows the debug output, so make that the readable one
+impl fmt::Debug for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for PinMismatch {}
+
+/**
+ * the servers' hosts only get in with one of the pinned keys somewhere in its chain,
+ * on top of the usual checks against `ca_certs` if there are any, otherwise the pin is the trust,
+ * a self signed certificate is fine and only the leaf's key counts. anything else (an https proxy) needs `ca_certs`
+ */
+#[derive(Debug)]
+struct PinnedVerifier {
+    /** every server in `servers`, mirrors have to show a pinned key too
+     */
+    hosts: Vec<String>,
+    pins: Vec<String>,
+    provider: Arc<CryptoProvider>,
+    roots: Option<Arc<WebPkiServerVerifier>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(roots) = &self.roots {
+            roots.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+
+        let host = server_name.to_str();
+        if !self.hosts.iter().any(|h| *h == host) {
+            if self.roots.is_none() {
+                return Err(rustls::Error::General(format!("only {} is pinned and theres no ca_certs to check {} with", self.hosts.join(", "), host)));
+            }
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        // without roots nothing checked the name yet
+        rustls::client::verify_server_name(&ParsedCertificate::try_from(end_entity)?, server_name)?;
+
+        // the intermediates are just whatever the server sent, they only mean something once the roots checked the chain.
+        // otherwise anyone could sign their own leaf and tack the real certificate on behind it
+        let chain = if self.roots.is_some() { intermediates } else { &[] };
+        let pinned = std::iter::once(end_entity)
+            .chain(chain)
+            .filter_map(spki_pin)
+            .any(|pin| self.pins.contains(&pin));
+        if !pinned {
+            let got = spki_pin(end_entity).unwrap_or_default();
+            let err = PinMismatch(format!("{} showed the key {} which {}", host, got, PIN_MISMATCH));
+            return Err(CertificateError::Other(OtherError(Arc::new(err))).into());
+        }
+
+        return Ok(ServerCertVerified::assertion());
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/**
+ * native tls has no way to look at the certificate before the request goes out with the password in it,
+ * so with pins the client runs on rustls instead, with the same ca_certs, client cert and min version
+ */
+pub fn pinned_config(config: &ConfigFile) -> ClientConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let hosts = config.servers.iter()
+        .map(|server| Url::parse(&server.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.trim_matches(|c| c == '[' || c == ']').to_string()))
+            .unwrap_or_else(|| panic!("cant pin {}, theres no host in it", server.url)))
+        .collect();
+
+    let mut store = RootCertStore::empty();
+    for path in &config.ca_certs {
+        for cert in CertificateDer::pem_file_iter(path).unwrap_or_else(|err| panic!("couldnt read ca cert {}: {:?}", path, err)) {
+            let cert = cert.unwrap_or_else(|err| panic!("{} isnt a pem certificate: {:?}", path, err));
+            store.add(cert).unwrap_or_else(|err| panic!("cant use {} as a root: {}", path, err));
+        }
+    }
+    let roots = if store.is_empty() {
+        None
+    } else {
+        Some(WebPkiServerVerifier::builder_with_provider(Arc::new(store), provider.clone()).build().expect("couldnt set up the ca_certs"))
+    };
+
+    // rustls doesnt do anything older than 1.2 anyway
+    let versions = match config.tls_min_version {
+        Some(TlsVersion::Tls13) => vec![&rustls::version::TLS13],
+        _ => vec![&rustls::version::TLS12, &rustls::version::TLS13],
+    };
+
+    let verifier = PinnedVerifier {
+        hosts,
+        pins: config.pins.iter().map(|p| p.to_lowercase()).collect(),
+        provider: provider.clone(),
+        roots,
+    };
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&versions)
+        .expect("ring does tls 1.2 and 1.3")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let chain = CertificateDer::pem_file_iter(cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .unwrap_or_else(|err| panic!("couldnt read client cert {}: {:?}", cert_path, err));
+            let key = PrivateKeyDer::from_pem_file(key_path).unwrap_or_else(|err| panic!("couldnt read client key {}: {:?}", key_path, err));
+
+            builder.with_client_auth_cert(chain, key)
+                .unwrap_or_else(|err| panic!("{} and {} dont make a client identity: {}", cert_path, key_path, err))
+        },
+        _ => builder.with_no_client_auth(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rcgen::CertifiedKey;
+    use rustls::pki_types::PrivatePkcs8KeyDer;
+    use rustls::ServerConfig;
+    use rustls::ServerConnection;
+    use rustls::StreamOwned;
+
+    use super::*;
+    use crate::modules::network::build_client;
+    use crate::modules::network::retry::NetError;
+
+    const PASSWORD: &str = "hunter2";
+
+    fn self_signed() -> CertifiedKey {
+        rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap()
+    }
+
+    /** serves one connection with `chain`, hands back everything the client managed to send
+     */
+    fn serve(chain: Vec<CertificateDer<'static>>, key: &CertifiedKey) -> (u16, thread::JoinHandle<Vec<u8>>) {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.key_pair.serialize_der()));
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut stream = StreamOwned::new(ServerConnection::new(Arc::new(config)).unwrap(), tcp);
+            let mut got = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !got.ends_with(PASSWORD.as_bytes()) {
+                match stream.read(&mut buf) {
+                    Ok(n) if n > 0 => got.extend_from_slice(&buf[..n]),
+                    _ => return got,
+                }
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            let _ = stream.flush();
+            return got;
+        });
+
+        return (port, server);
+    }
+
+    fn post(port: u16, pins: &[String]) -> Result<u16, NetError> {
+        let config: ConfigFile = serde_json::from_value(serde_json::json!({
+            "username": "a",
+            "password": PASSWORD,
+            "lobotomize": true,
+            "request_timeout_ms": 5000,
+            "servers": [{ "url": format!("https://localhost:{}", port) }],
+            "pins": pins,
+        })).unwrap();
+
+        return build_client(&config)
+            .post(format!("https://localhost:{}/v1/userhashget", port))
+            .body(PASSWORD)
+            .send()
+            .map(|res| res.status().as_u16())
+            .map_err(NetError::classify);
+    }
+
+    #[test]
+    fn right_pin_connects() {
+        let server = self_signed();
+        let pin = spki_pin(server.cert.der()).unwrap();
+        let (port, handle) = serve(vec![server.cert.der().clone()], &server);
+
+        assert_eq!(post(port, &[pin]).unwrap(), 200);
+        assert!(handle.join().unwrap().ends_with(PASSWORD.as_bytes()));
+    }
+
+    #[test]
+    fn wrong_pin_sends_nothing() {
+        let server = self_signed();
+        let other = spki_pin(self_signed().cert.der()).unwrap();
+        let (port, handle) = serve(vec![server.cert.der().clone()], &server);
+
+        assert!(matches!(post(port, &[other]), Err(NetError::PinMismatch(_))));
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn real_cert_tacked_on_as_intermediate_isnt_pinned() {
+        let real = self_signed();
+        let attacker = self_signed();
+        let pin = spki_pin(real.cert.der()).unwrap();
+        let (port, handle) = serve(vec![attacker.cert.der().clone(), real.cert.der().clone()], &attacker);
+
+        assert!(matches!(post(port, &[pin]), Err(NetError::PinMismatch(_))));
+        assert!(handle.join().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+// keeps asking the server for the hash in the background, it can change without us submitting anything
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::PoolClient;
+
+pub struct Watcher {
+    /** the hash we're mining, so a change we made ourselves doesnt get reported back
+     */
+    current: Arc<Mutex<String>>,
+    changes: Receiver<String>,
+}
+
+impl Watcher {
+    pub fn new<P: PoolClient + Send + Sync + ?Sized + 'static>(pool: Arc<P>, interval: Duration, hash: &str) -> Watcher {
+        let current = Arc::new(Mutex::new(hash.to_string()));
+        let (sender, changes) = mpsc::channel::<String>();
+
+        let watched = current.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+
+                let before = watched.lock().unwrap().clone();
+                let hash = match pool.get_hash() {
+                    Ok(h) => h,
+                    Err(err) => {
+                        println!("watcher couldnt get the hash: {}", err);
+                        continue;
+                    },
+                };
+
+                // main moved on while we were asking, this answer might be older than what it has
+                let mut current = watched.lock().unwrap();
+                if *current != before || *current == hash {
+                    continue;
+                }
+
+                *current = hash.clone();
+                if sender.send(hash).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Watcher {
+            current,
+            changes,
+        }
+    }
+
+    pub fn set_current(&self, hash: &str) {
+        *self.current.lock().unwrap() = hash.to_string();
+    }
+
+    /** a hash the server switched to behind our back
+     */
+    pub fn try_changed(&self) -> Option<String> {
+        self.changes.try_iter().last()
+    }
+}
@@ -0,0 +1,246 @@
+// the miner end of the work proxy, hashes come in over one tcp connection and finds go back out the same way
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpStream;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::modules::nonce::NonceEncoding;
+use super::retry::NetError;
+use super::workproxy::Call;
+use super::workproxy::Notify;
+use super::workproxy::Reply;
+use super::workproxy::Request;
+use super::workproxy::Subscribed;
+use super::PoolClient;
+use super::SubmitOutcome;
+
+const RECONNECT: Duration = Duration::from_secs(5);
+
+/** the proxy retries upstream before it answers, so this is a lot longer than a request timeout
+ */
+const SUBMIT_WAIT: Duration = Duration::from_secs(300);
+
+pub struct WorkClient {
+    address: String,
+    writer: Mutex<TcpStream>,
+    next_id: AtomicU64,
+    /** calls still waiting on their reply
+     */
+    waiting: Mutex<HashMap<u64, Sender<Reply>>>,
+    /** job id to its current hash, kept up to date by the notifies
+     */
+    hashes: Mutex<HashMap<String, String>>,
+    connected: AtomicBool,
+    pub subscribed: Subscribed,
+}
+
+impl WorkClient {
+    /** keeps trying until the proxy is there, the rig id in `subscribed` is ours for as long as we're connected
+     */
+    pub fn connect(address: &str) -> Arc<WorkClient> {
+        let (stream, reader, subscribed) = loop {
+            match handshake(address, None) {
+                Ok(connection) => break connection,
+                Err(err) => {
+                    println!("couldnt subscribe to the work proxy at {}, trying again in {}s: {}", address, RECONNECT.as_secs(), err);
+                    thread::sleep(RECONNECT);
+                },
+            }
+        };
+
+        let client = Arc::new(WorkClient {
+            address: address.to_string(),
+            writer: Mutex::new(stream),
+            next_id: AtomicU64::new(1),
+            waiting: Mutex::new(HashMap::new()),
+            hashes: Mutex::new(subscribed.jobs.iter().map(|j| (j.job_id.clone(), j.hash.clone())).collect()),
+            connected: AtomicBool::new(true),
+            subscribed,
+        });
+
+        let listener = client.clone();
+        thread::spawn(move || listener.listen(reader));
+
+        return client;
+    }
+
+    /** reads whatever the proxy sends, and gets the connection back when it drops
+     */
+    fn listen(&self, mut reader: BufReader<TcpStream>) {
+        loop {
+            let mut line = String::new();
+            let read = reader.read_line(&mut line);
+            if matches!(read, Ok(n) if n > 0) {
+                self.handle(&line);
+                continue;
+            }
+
+            println!("lost the work proxy, reconnecting");
+            self.connected.store(false, Ordering::SeqCst);
+            // dropping the senders wakes up everything thats waiting with an error
+            self.waiting.lock().unwrap().clear();
+
+            reader = self.reconnect();
+        }
+    }
+
+    fn handle(&self, line: &str) {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(m) => m,
+            Err(err) => {
+                println!("work proxy sent something thats not json: {}", err);
+                return;
+            },
+        };
+
+        if message.get("id").is_some() {
+            if let Ok(reply) = serde_json::from_value::<Reply>(message) {
+                if let Some(sender) = self.waiting.lock().unwrap().remove(&reply.id) {
+                    let _ = sender.send(reply);
+                }
+            }
+            return;
+        }
+
+        if let Ok(notify) = serde_json::from_value::<Notify>(message) {
+            self.hashes.lock().unwrap().insert(notify.params.job_id, notify.params.hash);
+        }
+    }
+
+    fn reconnect(&self) -> BufReader<TcpStream> {
+        loop {
+            thread::sleep(RECONNECT);
+
+            let (stream, reader, subscribed) = match handshake(&self.address, Some(self.subscribed.rig_id)) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    println!("work proxy still isnt there: {}", err);
+                    continue;
+                },
+            };
+
+            // the gpus are already mining with the old prefix, carrying on with a new one could overlap someone else
+            if subscribed.rig_id != self.subscribed.rig_id || subscribed.nonce_encoding != self.subscribed.nonce_encoding || subscribed.rig_digits != self.subscribed.rig_digits {
+                println!("work proxy gave us rig {} instead of {}, restart to mine with the new prefix", subscribed.rig_id, self.subscribed.rig_id);
+                process::exit(1);
+            }
+
+            *self.hashes.lock().unwrap() = subscribed.jobs.iter().map(|j| (j.job_id.clone(), j.hash.clone())).collect();
+            *self.writer.lock().unwrap() = stream;
+            self.connected.store(true, Ordering::SeqCst);
+            println!("back on the work proxy");
+
+            return reader;
+        }
+    }
+
+    fn call(&self, call: Call, wait: Duration) -> Result<Value, NetError> {
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(NetError::Connect(String::from("work proxy is down")));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel();
+        self.waiting.lock().unwrap().insert(id, sender);
+
+        let line = serde_json::to_string(&Request { id, call }).unwrap() + "\n";
+        if let Err(err) = self.writer.lock().unwrap().write_all(line.as_bytes()) {
+            self.waiting.lock().unwrap().remove(&id);
+            return Err(NetError::Connect(err.to_string()));
+        }
+
+        let reply = match receiver.recv_timeout(wait) {
+            Ok(r) => r,
+            Err(RecvTimeoutError::Timeout) => {
+                self.waiting.lock().unwrap().remove(&id);
+                return Err(NetError::Timeout(String::from("work proxy didnt answer")));
+            },
+            Err(RecvTimeoutError::Disconnected) => return Err(NetError::Connect(String::from("work proxy went away"))),
+        };
+
+        match (reply.result, reply.error) {
+            (_, Some(err)) => Err(NetError::Other(format!("work proxy: {}", err))),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(NetError::BadJson(String::from("work proxy replied with nothing"))),
+        }
+    }
+}
+
+/** connects and subscribes, notifies cant come in before the reply so the first line is it
+ */
+fn handshake(address: &str, rig_id: Option<u64>) -> io::Result<(TcpStream, BufReader<TcpStream>, Subscribed)> {
+    let mut stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let request = Request { id: 0, call: Call::Subscribe { rig_id } };
+    stream.write_all((serde_json::to_string(&request).unwrap() + "\n").as_bytes())?;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "proxy hung up"));
+    }
+    let reply: Reply = serde_json::from_str(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if let Some(err) = reply.error {
+        return Err(io::Error::other(err));
+    }
+    let subscribed = reply.result
+        .and_then(|result| serde_json::from_value(result).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "subscribe reply doesnt make sense"))?;
+
+    return Ok((stream, reader, subscribed));
+}
+
+/** one of the proxy's jobs, looks like the server to a session
+ */
+pub struct ProxyJob {
+    client: Arc<WorkClient>,
+    job_id: String,
+}
+
+impl ProxyJob {
+    pub fn new(client: Arc<WorkClient>, job_id: &str) -> ProxyJob {
+        ProxyJob {
+            client,
+            job_id: job_id.to_string(),
+        }
+    }
+}
+
+impl PoolClient for ProxyJob {
+    fn get_hash(&self) -> Result<String, NetError> {
+        if !self.client.connected.load(Ordering::SeqCst) {
+            return Err(NetError::Connect(String::from("work proxy is down")));
+        }
+
+        return self.client.hashes.lock().unwrap()
+            .get(&self.job_id)
+            .cloned()
+            .ok_or_else(|| NetError::Refused(format!("work proxy has no job called {}", self.job_id)));
+    }
+
+    fn submit(&self, nonce: u64, encoding: NonceEncoding) -> Result<SubmitOutcome, NetError> {
+        let call = Call::Submit {
+            job_id: self.job_id.clone(),
+            nonce,
+            encoding,
+        };
+        let result = self.client.call(call, SUBMIT_WAIT)?;
+
+        return serde_json::from_value(result).map_err(|err| NetError::BadJson(err.to_string()));
+    }
+}
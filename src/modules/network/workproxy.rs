@@ -0,0 +1,511 @@
+// hands the server's jobs out to miners on the lan, so a whole farm is one set of credentials and one upstream connection
+// newline separated json both ways: miners send `subscribe` then `submit`s, the proxy answers those and sends `notify` when a hash changes
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::process;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::jason::ConfigFile;
+use crate::modules::ledger::Ledger;
+use crate::modules::nonce::NonceEncoding;
+use super::build_client;
+use super::failover::Endpoints;
+use super::gabserver::GabServer;
+use super::ratelimit::Limiter;
+use super::retry::NetError;
+use super::PoolClient;
+use super::SubmitOutcome;
+use super::OUTAGE_POLL;
+
+/** a miner thats stopped reading doesnt get to hold up everyone elses notifications
+ */
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/** a miner asking for something, `id` comes back on the reply
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    pub id: u64,
+    #[serde(flatten)]
+    pub call: Call,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+pub enum Call {
+    /** first thing on a connection, `rig_id` asks for the prefix it had before a reconnect
+     */
+    Subscribe {
+        rig_id: Option<u64>,
+    },
+    Submit {
+        job_id: String,
+        nonce: u64,
+        encoding: NonceEncoding,
+    },
+}
+
+/** `result` when it worked, `error` when it didnt
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reply {
+    pub id: u64,
+    #[serde(default)]
+    pub result: Option<Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/** the proxy telling every miner a job has a new hash, theres no id on these
+ */
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Notify {
+    pub method: String,
+    pub params: JobHash,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobHash {
+    pub job_id: String,
+    pub hash: String,
+}
+
+/** one account on one threadid upstream
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub job_id: String,
+    pub username: String,
+    pub thread_id: u32,
+    pub weight: u32,
+    pub hash: String,
+}
+
+/** what a miner has to mine with so its nonces never overlap with the other miners'
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subscribed {
+    pub rig_id: u64,
+    pub rig_digits: u32,
+    pub nonce_encoding: NonceEncoding,
+    pub jobs: Vec<Job>,
+}
+
+struct Upstream {
+    id: String,
+    username: String,
+    thread_id: u32,
+    weight: u32,
+    /** waits on the account's submit limit itself, the whole farm shares it
+     */
+    pool: GabServer,
+    ledger: Arc<Mutex<Ledger>>,
+    /** held through a submit so two miners racing on the same hash dont both go upstream with it
+     */
+    submitting: Mutex<()>,
+    /**
+     * only ever held for a moment and never while taking another lock,
+     * `miners` then `hash` is the one order they go in
+     */
+    hash: Mutex<String>,
+}
+
+struct Miner {
+    rig_id: u64,
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+struct Proxy {
+    jobs: Vec<Upstream>,
+    miners: Mutex<Vec<Miner>>,
+    nonce_encoding: NonceEncoding,
+    rig_digits: u32,
+    /** how many prefixes `rig_digits` leaves room for
+     */
+    rigs: u64,
+}
+
+impl Proxy {
+    /**
+     * gives the miner a rig id nobody else has and the jobs as they are right now.
+     * the reply goes out under the miners lock so a notify cant get in ahead of it
+     */
+    fn subscribe(&self, id: u64, wanted: Option<u64>, writer: Arc<Mutex<TcpStream>>) -> Option<u64> {
+        let mut miners = self.miners.lock().unwrap();
+        let taken = |rig: u64| miners.iter().any(|m| m.rig_id == rig);
+
+        let rig_id = match wanted {
+            Some(rig) if rig < self.rigs && !taken(rig) => rig,
+            _ => match (0..self.rigs).find(|rig| !taken(*rig)) {
+                Some(rig) => rig,
+                None => {
+                    send(&writer, &error_reply(id, "no rig ids left, raise rig_digits on the proxy"));
+                    return None;
+                },
+            },
+        };
+
+        let subscribed = Subscribed {
+            rig_id,
+            rig_digits: self.rig_digits,
+            nonce_encoding: self.nonce_encoding,
+            jobs: self.jobs.iter().map(|job| Job {
+                job_id: job.id.clone(),
+                username: job.username.clone(),
+                thread_id: job.thread_id,
+                weight: job.weight,
+                hash: job.hash.lock().unwrap().clone(),
+            }).collect(),
+        };
+        let reply = Reply {
+            id,
+            result: Some(serde_json::to_value(subscribed).unwrap()),
+            error: None,
+        };
+        if !send(&writer, &reply) {
+            return None;
+        }
+
+        miners.push(Miner { rig_id, writer });
+        println!("rig {} subscribed, {} miners", rig_id, miners.len());
+
+        return Some(rig_id);
+    }
+
+    fn unsubscribe(&self, rig_id: u64) {
+        let mut miners = self.miners.lock().unwrap();
+        miners.retain(|m| m.rig_id != rig_id);
+        println!("rig {} left, {} miners", rig_id, miners.len());
+    }
+
+    /**
+     * tells everyone the job's hash as it is now, anyone who cant be written to gets dropped.
+     * reading it under the miners lock means whoever broadcasts last sends the newest one
+     */
+    fn broadcast(&self, job: &Upstream) {
+        let mut miners = self.miners.lock().unwrap();
+        let notify = Notify {
+            method: String::from("notify"),
+            params: JobHash {
+                job_id: job.id.clone(),
+                hash: job.hash.lock().unwrap().clone(),
+            },
+        };
+
+        miners.retain(|m| send(&m.writer, &notify));
+    }
+
+    fn submit(&self, job_id: &str, nonce: u64, encoding: NonceEncoding) -> Result<SubmitOutcome, String> {
+        let job = self.jobs.iter()
+            .find(|j| j.id == job_id)
+            .ok_or_else(|| format!("no job called {}", job_id))?;
+
+        // the limit gets waited on inside, that only holds up other submits on this job and theyd be waiting on it too
+        let _turn = job.submitting.lock().unwrap();
+        let outcome = job.pool.submit(nonce, encoding).map_err(|err| err.to_string())?;
+        match &outcome {
+            SubmitOutcome::Accepted { reward, balance, newhash } => {
+                println!("{}: nonce {} went through, reward {}, balance {}", job.id, encoding.format(nonce), reward, balance);
+                println!("servers: {}", job.pool.health().unwrap_or_default());
+                job.ledger.lock().unwrap().record(&job.username, job.thread_id, *reward, *balance);
+                *job.hash.lock().unwrap() = newhash.clone();
+                self.broadcast(job);
+            },
+            SubmitOutcome::StaleHash | SubmitOutcome::Unknown(_) => {
+                if let Ok(new_hash) = job.pool.get_hash() {
+                    let moved = {
+                        let mut hash = job.hash.lock().unwrap();
+                        let moved = new_hash != *hash;
+                        *hash = new_hash;
+                        moved
+                    };
+                    if moved {
+                        self.broadcast(job);
+                    }
+                }
+            },
+            SubmitOutcome::BadAuth => println!("server doesnt take the password for {}, fix config.json", job.username),
+            SubmitOutcome::BadNonce => {},
+        }
+
+        return Ok(outcome);
+    }
+
+    /** the hash can move without anyone here submitting, same dance as the watcher
+     */
+    fn watch(&self, index: usize, interval: Duration) {
+        let job = &self.jobs[index];
+
+        loop {
+            thread::sleep(interval);
+
+            let before = job.hash.lock().unwrap().clone();
+            let new_hash = match job.pool.get_hash() {
+                Ok(h) => h,
+                Err(err) => {
+                    println!("{}: couldnt get the hash: {}", job.id, err);
+                    continue;
+                },
+            };
+
+            // a submit moved it while we were asking, this answer might be older than that
+            {
+                let mut hash = job.hash.lock().unwrap();
+                if *hash != before || *hash == new_hash {
+                    continue;
+                }
+                println!("{}: server moved from {} to {}", job.id, hash, new_hash);
+                *hash = new_hash;
+            }
+            self.broadcast(job);
+        }
+    }
+}
+
+/** `gpu-miner proxy`, never returns
+ */
+pub fn run(config: &ConfigFile) {
+    let endpoints = Endpoints::new(build_client(config), config);
+
+    let limiters = Limiter::per_account(config.accounts.iter().map(|a| a.username.as_str()), config.submit_limit);
+    let ledger = Arc::new(Mutex::new(Ledger::open()));
+    let mut jobs = Vec::new();
+    for thread_id in &config.thread_ids {
+        for account in &config.accounts {
+            let pool = GabServer::new(endpoints.clone(), limiters[&account.username].clone(), config, account, *thread_id);
+            let id = format!("{}/{}", account.username, thread_id);
+            let hash = loop {
+                match pool.get_hash() {
+                    Ok(hash) => break hash,
+                    Err(NetError::BadAuth) => {
+                        println!("server doesnt take the password for {}, fix config.json", account.username);
+                        process::exit(1);
+                    },
+                    Err(err) => {
+                        println!("{}: couldnt get a hash, trying again in {}s: {}", id, OUTAGE_POLL.as_secs(), err);
+                        thread::sleep(OUTAGE_POLL);
+                    },
+                }
+            };
+
+            jobs.push(Upstream {
+                id,
+                username: account.username.clone(),
+                thread_id: *thread_id,
+                weight: account.weight,
+                pool,
+                ledger: ledger.clone(),
+                submitting: Mutex::new(()),
+                hash: Mutex::new(hash),
+            });
+        }
+    }
+
+    // checks rig_digits fits the encoding
+    config.nonce_encoding.rig_prefix(0, config.rig_digits);
+    let rigs = config.nonce_encoding.rigs(config.rig_digits);
+
+    let proxy = Arc::new(Proxy {
+        jobs,
+        miners: Mutex::new(Vec::new()),
+        nonce_encoding: config.nonce_encoding,
+        rig_digits: config.rig_digits,
+        rigs,
+    });
+
+    if config.challenge_poll_ms > 0 {
+        for index in 0..proxy.jobs.len() {
+            let proxy = proxy.clone();
+            let interval = Duration::from_millis(config.challenge_poll_ms);
+            thread::spawn(move || proxy.watch(index, interval));
+        }
+    }
+
+    let listener = TcpListener::bind(&config.work_proxy_listen).expect("couldnt listen for miners");
+    println!("work proxy on {}, {} jobs, room for {} rigs", config.work_proxy_listen, proxy.jobs.len(), rigs);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let proxy = proxy.clone();
+        thread::spawn(move || serve(&proxy, stream));
+    }
+}
+
+/** one miner's connection, until it hangs up or sends garbage
+ */
+fn serve(proxy: &Proxy, stream: TcpStream) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let writer = match stream.try_clone() {
+        Ok(s) => Arc::new(Mutex::new(s)),
+        Err(_) => return,
+    };
+
+    let mut rig_id = None;
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(err) => {
+                println!("{} sent something thats not a request, hanging up: {}", peer, err);
+                break;
+            },
+        };
+
+        let reply = match request.call {
+            Call::Subscribe { .. } if rig_id.is_some() => error_reply(request.id, "already subscribed"),
+            Call::Subscribe { rig_id: wanted } => {
+                rig_id = proxy.subscribe(request.id, wanted, writer.clone());
+                if rig_id.is_none() {
+                    break;
+                }
+                continue;
+            },
+            Call::Submit { .. } if rig_id.is_none() => error_reply(request.id, "subscribe first"),
+            Call::Submit { job_id, nonce, encoding } => match proxy.submit(&job_id, nonce, encoding) {
+                Ok(outcome) => Reply {
+                    id: request.id,
+                    result: Some(serde_json::to_value(outcome).unwrap()),
+                    error: None,
+                },
+                Err(err) => error_reply(request.id, &err),
+            },
+        };
+
+        if !send(&writer, &reply) {
+            break;
+        }
+    }
+
+    if let Some(rig_id) = rig_id {
+        proxy.unsubscribe(rig_id);
+    }
+}
+
+fn error_reply(id: u64, error: &str) -> Reply {
+    Reply {
+        id,
+        result: None,
+        error: Some(error.to_string()),
+    }
+}
+
+/** false if the miner is gone
+ */
+pub fn send<T: Serialize>(writer: &Mutex<TcpStream>, message: &T) -> bool {
+    let line = serde_json::to_string(message).unwrap() + "\n";
+    let mut writer = writer.lock().unwrap();
+
+    return writer.write_all(line.as_bytes()).and_then(|_| writer.flush()).is_ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(rig_digits: u32) -> Arc<Proxy> {
+        Arc::new(Proxy {
+            jobs: Vec::new(),
+            miners: Mutex::new(Vec::new()),
+            nonce_encoding: NonceEncoding::Hex,
+            rig_digits,
+            rigs: NonceEncoding::Hex.rigs(rig_digits),
+        })
+    }
+
+    /** both ends of a connection, the proxy's and the miner's
+     */
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let miner = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (ours, _) = listener.accept().unwrap();
+
+        return (ours, miner);
+    }
+
+    fn subscribe(proxy: &Proxy, wanted: Option<u64>) -> (Option<u64>, Reply) {
+        let (ours, miner) = connection();
+        let rig_id = proxy.subscribe(1, wanted, Arc::new(Mutex::new(ours)));
+
+        let mut line = String::new();
+        BufReader::new(miner).read_line(&mut line).unwrap();
+        return (rig_id, serde_json::from_str(&line).unwrap());
+    }
+
+    #[test]
+    fn every_rig_gets_its_own_id() {
+        let proxy = proxy(1);
+        assert_eq!(proxy.rigs, 16);
+
+        assert_eq!(subscribe(&proxy, None).0, Some(0));
+        assert_eq!(subscribe(&proxy, None).0, Some(1));
+        assert_eq!(subscribe(&proxy, Some(5)).0, Some(5));
+        assert_eq!(subscribe(&proxy, Some(0)).0, Some(2), "0 is taken");
+        assert_eq!(subscribe(&proxy, Some(16)).0, Some(3), "16 doesnt fit in one digit");
+
+        proxy.unsubscribe(0);
+        let (rig_id, reply) = subscribe(&proxy, Some(0));
+        assert_eq!(rig_id, Some(0), "a reconnect gets its old id back");
+        let subscribed: Subscribed = serde_json::from_value(reply.result.unwrap()).unwrap();
+        assert_eq!(subscribed.rig_id, 0);
+        assert_eq!(subscribed.rig_digits, 1);
+
+        let subscribed = proxy.miners.lock().unwrap().len();
+        for _ in subscribed..16 {
+            assert!(subscribe(&proxy, None).0.is_some());
+        }
+        let (rig_id, reply) = subscribe(&proxy, None);
+        assert_eq!(rig_id, None);
+        assert!(reply.error.unwrap().contains("no rig ids left"));
+        assert_eq!(proxy.miners.lock().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn miners_have_to_subscribe_once_before_submitting() {
+        let proxy = proxy(2);
+        let (ours, miner) = connection();
+        let serving = {
+            let proxy = proxy.clone();
+            thread::spawn(move || serve(&proxy, ours))
+        };
+
+        let mut writer = miner.try_clone().unwrap();
+        let mut reader = BufReader::new(miner);
+        let mut call = |request: &str| -> Reply {
+            writer.write_all((request.to_string() + "\n").as_bytes()).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            return serde_json::from_str(&line).unwrap();
+        };
+
+        let submit = r#"{"id":1,"method":"submit","params":{"job_id":"a/1","nonce":7,"encoding":"hex"}}"#;
+        assert_eq!(call(submit).error.as_deref(), Some("subscribe first"));
+
+        let reply = call(r#"{"id":2,"method":"subscribe","params":{"rig_id":42}}"#);
+        assert_eq!(reply.id, 2);
+        assert_eq!(reply.result.unwrap()["rig_id"], 42);
+        assert_eq!(call(r#"{"id":3,"method":"subscribe","params":{"rig_id":null}}"#).error.as_deref(), Some("already subscribed"));
+        assert_eq!(call(submit).error.as_deref(), Some("no job called a/1"));
+
+        // garbage gets it hung up on and its rig id freed
+        writer.write_all(b"hello\n").unwrap();
+        serving.join().unwrap();
+        assert!(proxy.miners.lock().unwrap().is_empty());
+    }
+}
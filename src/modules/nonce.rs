@@ -0,0 +1,306 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/** digits used by the text encodings, base36 is just the first 36 of these
+ */
+const ALPHABET: &[u8; 62] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/**
+ * how a nonce is written into the message after the challenge hash
+ * text encodings are fixed width and zero padded, binary is the raw
+ * little-endian u64 and goes to the server as hex
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NonceEncoding {
+    #[default]
+    Decimal,
+    Hex,
+    Base36,
+    Base62,
+    Binary,
+}
+
+impl NonceEncoding {
+    pub fn radix(&self) -> u64 {
+        match self {
+            NonceEncoding::Decimal => 10,
+            NonceEncoding::Hex => 16,
+            NonceEncoding::Base36 => 36,
+            NonceEncoding::Base62 => 62,
+            NonceEncoding::Binary => 256,
+        }
+    }
+
+    /** bytes the nonce takes up in the message
+     */
+    pub fn width(&self) -> usize {
+        match self {
+            NonceEncoding::Decimal => 20,
+            NonceEncoding::Hex => 16,
+            NonceEncoding::Base36 => 13,
+            NonceEncoding::Base62 => 11,
+            NonceEncoding::Binary => 8,
+        }
+    }
+
+    /** low digits the kernel fills in from the invocation id,
+     * enough to cover MAX_INVOCATIONS
+     */
+    pub fn gpu_digits(&self) -> u32 {
+        match self {
+            NonceEncoding::Decimal => 10,
+            NonceEncoding::Hex => 6,
+            NonceEncoding::Base36 => 5,
+            NonceEncoding::Base62 => 4,
+            NonceEncoding::Binary => 4,
+        }
+    }
+
+    /** how far the nonce moves per dispatch
+     */
+    pub fn stride(&self) -> u64 {
+        self.radix().pow(self.gpu_digits())
+    }
+
+    /** top digits the rig prefix and dispatches share, the ones above are never touched
+     * decimal keeps its leading 10 and base36/62 skip the top digit a u64 cant fill
+     */
+    fn free_digits(&self) -> u32 {
+        match self {
+            NonceEncoding::Decimal => 18,
+            NonceEncoding::Hex => 16,
+            NonceEncoding::Base36 => 12,
+            NonceEncoding::Base62 => 10,
+            NonceEncoding::Binary => 8,
+        }
+    }
+
+    pub fn max_rig_digits(&self) -> u32 {
+        self.free_digits() - self.gpu_digits() - 1
+    }
+
+    fn prefix_place(&self, rig_digits: u32) -> u128 {
+        (self.radix() as u128).pow(self.free_digits() - rig_digits)
+    }
+
+    /** how many rig ids `rig_digits` leaves room for
+     */
+    pub fn rigs(&self, rig_digits: u32) -> u64 {
+        (self.radix() as u128).pow(rig_digits).min(u64::MAX as u128) as u64
+    }
+
+    /** added to `start()` so the top `rig_digits` free digits spell out the rig id
+     */
+    pub fn rig_prefix(&self, rig_id: u64, rig_digits: u32) -> u64 {
+        if rig_digits > self.max_rig_digits() {
+            panic!("rig_digits can be at most {} for {:?} nonces", self.max_rig_digits(), self);
+        }
+
+        return ((rig_id % self.rigs(rig_digits)) as u128 * self.prefix_place(rig_digits)) as u64;
+    }
+
+    /** dispatches that fit below the rig prefix
+     */
+    pub fn dispatch_limit(&self, rig_digits: u32) -> u64 {
+        (self.prefix_place(rig_digits) / self.stride() as u128) as u64
+    }
+
+    /** first nonce to mine; decimal starts at 10^19 so every nonce has all 20 digits
+     */
+    pub fn start(&self) -> u64 {
+        match self {
+            NonceEncoding::Decimal => 10_000_000_000_000_000_000,
+            _ => 0,
+        }
+    }
+
+    pub fn encode(&self, nonce: u64) -> Vec<u8> {
+        if *self == NonceEncoding::Binary {
+            return nonce.to_le_bytes().to_vec();
+        }
+
+        let radix = self.radix();
+        let mut bytes = vec![ALPHABET[0]; self.width()];
+        let mut rest = nonce;
+
+        for i in (0..self.width()).rev() {
+            bytes[i] = ALPHABET[(rest % radix) as usize];
+            rest /= radix;
+        }
+
+        return bytes;
+    }
+
+    /** printable form of the nonce, binary nonces are shown as hex bytes
+     */
+    pub fn format(&self, nonce: u64) -> String {
+        match self {
+            NonceEncoding::Binary => hex::encode(self.encode(nonce)),
+            _ => String::from_utf8(self.encode(nonce)).unwrap(),
+        }
+    }
+
+    /**
+     * nonce as a json value for the server; decimal goes as a plain integer, everything else as the string that got hashed.
+     * binary goes as the hex of its 8 bytes, the integer would get hashed as decimal digits
+     */
+    pub fn to_json(self, nonce: u64) -> serde_json::Value {
+        match self {
+            NonceEncoding::Decimal => serde_json::Value::from(nonce),
+            _ => serde_json::Value::from(self.format(nonce)),
+        }
+    }
+
+    /** the second sha1 block for `nonce`: nonce, padding and message length
+     */
+    pub fn message_block(&self, nonce: u64) -> [u32; 16] {
+        let mut block: [u32; 16] = [0; 16];
+
+        self.pack(self.encode(nonce), &mut block);
+        return block;
+    }
+
+    /**
+     * writes the second sha1 block for `nonce` followed by the kernel parameters
+     * block[0..16]: message_block with the gpu digits zeroed
+     * block[16..19]: radix, width, gpu digits
+     */
+    pub fn write_block(&self, nonce: u64, block: &mut [u32]) {
+        let mut bytes = self.encode(nonce);
+        let digits = self.gpu_digits() as usize;
+
+        // the kernel ors its digits into these
+        if *self == NonceEncoding::Binary {
+            bytes[..digits].fill(0);
+        } else {
+            let width = bytes.len();
+            bytes[(width - digits)..].fill(0);
+        }
+
+        self.pack(bytes, block);
+        block[16] = self.radix() as u32;
+        block[17] = self.width() as u32;
+        block[18] = self.gpu_digits();
+    }
+
+    fn pack(&self, mut bytes: Vec<u8>, block: &mut [u32]) {
+        bytes.push(0x80);
+        bytes.resize(60, 0);
+        // the challenge hash is one full block in front of us
+        bytes.extend_from_slice(&(((64 + self.width()) * 8) as u32).to_be_bytes());
+
+        for i in 0..16 {
+            block[i] = u32::from_be_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::digest;
+
+    use super::*;
+    use crate::modules::helpers;
+
+    const HASH: &str = "8deda67f452dc5de673a01fad1580ca4429bc166a4e3dc5d3911535616327e32";
+
+    const ENCODINGS: [NonceEncoding; 5] = [
+        NonceEncoding::Decimal,
+        NonceEncoding::Hex,
+        NonceEncoding::Base36,
+        NonceEncoding::Base62,
+        NonceEncoding::Binary,
+    ];
+
+    /** sha1 of hash + nonce the boring way
+     */
+    fn cpu_sha1(encoding: NonceEncoding, nonce: u64) -> [u32; 5] {
+        let message = [HASH.as_bytes(), &encoding.encode(nonce)].concat();
+        let digest = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &message);
+        let mut words = [0u32; 5];
+        for (i, word) in digest.as_ref().chunks(4).enumerate() {
+            words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        return words;
+    }
+
+    /** what the shader does to write_block's output for invocation `id`
+     */
+    fn kernel(block: &[u32; 19], id: u32) -> [u32; 16] {
+        let mut words = [0u32; 16];
+        words.copy_from_slice(&block[..16]);
+        let (radix, width, digits) = (block[16], block[17], block[18]);
+
+        let mut rest = id;
+        for j in 0..digits {
+            let digit = rest % radix;
+            rest /= radix;
+            let pos = if radix == 256 { j } else { width - 1 - j };
+            let ch = if radix == 256 { digit as u8 } else { ALPHABET[digit as usize] };
+            words[(pos >> 2) as usize] |= (ch as u32) << (24 - 8 * (pos & 3));
+        }
+
+        return words;
+    }
+
+    fn nonces(encoding: NonceEncoding) -> Vec<u64> {
+        vec![encoding.start(), encoding.start() + 12345, encoding.start() + encoding.stride() * 77 + 61, u64::MAX]
+    }
+
+    #[test]
+    fn encode_is_fixed_width() {
+        for encoding in ENCODINGS {
+            for nonce in nonces(encoding) {
+                assert_eq!(encoding.encode(nonce).len(), encoding.width(), "{:?} {}", encoding, nonce);
+            }
+        }
+        assert_eq!(NonceEncoding::Decimal.format(10_000_000_000_000_012_345), "10000000000000012345");
+        assert_eq!(NonceEncoding::Hex.format(255), "00000000000000ff");
+        assert_eq!(NonceEncoding::Binary.format(1), "0100000000000000");
+    }
+
+    #[test]
+    fn message_block_matches_cpu_sha1() {
+        let prehash = helpers::sha1_prehash(HASH);
+
+        for encoding in ENCODINGS {
+            for nonce in nonces(encoding) {
+                let digest = helpers::sha1_compress(prehash, &encoding.message_block(nonce));
+                assert_eq!(digest, cpu_sha1(encoding, nonce), "{:?} {}", encoding, nonce);
+            }
+        }
+    }
+
+    #[test]
+    fn write_block_plus_kernel_digits_is_the_nonce() {
+        let prehash = helpers::sha1_prehash(HASH);
+
+        for encoding in ENCODINGS {
+            for dispatch in [0u64, 1, 77] {
+                let base = encoding.start() + dispatch * encoding.stride();
+                let mut block = [0u32; 19];
+                encoding.write_block(base, &mut block);
+
+                // ids only go as high as the invocations in a dispatch, not the whole stride
+                for id in [0u64, 1, 9, 10, 35, 61, 62, 255, 256, 4194303] {
+                    if id >= encoding.stride() {
+                        continue;
+                    }
+                    let words = kernel(&block, id as u32);
+                    assert_eq!(words, encoding.message_block(base + id), "{:?} {} {}", encoding, base, id);
+                    assert_eq!(helpers::sha1_compress(prehash, &words), cpu_sha1(encoding, base + id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn to_json_is_what_got_hashed() {
+        assert_eq!(NonceEncoding::Decimal.to_json(10_000_000_000_000_000_007), serde_json::json!(10_000_000_000_000_000_007u64));
+        assert_eq!(NonceEncoding::Hex.to_json(255), serde_json::json!("00000000000000ff"));
+        assert_eq!(NonceEncoding::Base62.to_json(61), serde_json::json!("0000000000Z"));
+        assert_eq!(NonceEncoding::Binary.to_json(0x0102), serde_json::json!("0201000000000000"));
+    }
+}
@@ -0,0 +1,171 @@
+// which dispatch gets mined when
+use std::ops::Range;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchOrder {
+    /** thread t mines one contiguous block, like always
+     */
+    #[default]
+    Sequential,
+    /** thread t mines t, t + threads, t + 2 * threads..
+     */
+    Strided,
+    /** keyed feistel shuffle of the whole space, rigs with different keys wander off in different directions
+     */
+    Feistel,
+}
+
+/**
+ * bijection from the planner's logical dispatch indices to the real ones, both in [0, total)
+ * the planner hands each thread a contiguous logical range so whatever the order,
+ * every dispatch is mined exactly once
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permutation {
+    order: SearchOrder,
+    key: u64,
+    total: u64,
+    threads: u64,
+}
+
+impl Permutation {
+    pub fn new(order: SearchOrder, key: u64, total: u64, threads: u64) -> Permutation {
+        Permutation {
+            order,
+            key,
+            total,
+            threads,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /** what scan logs from before there was a search order were mined with
+     */
+    pub fn sequential() -> Permutation {
+        Permutation::new(SearchOrder::Sequential, 0, 0, 1)
+    }
+
+    /** whether logical indices mean the same real dispatches in both, sequential ones always do
+     */
+    pub fn lines_up(&self, other: &Permutation) -> bool {
+        self == other || (self.order == SearchOrder::Sequential && other.order == SearchOrder::Sequential)
+    }
+
+    /** logical range for thread t, the first total % threads threads get one extra
+     */
+    pub fn thread_range(&self, t: u64) -> Range<u64> {
+        let q = self.total / self.threads;
+        let r = self.total % self.threads;
+        let start = t * q + t.min(r);
+        let len = if t < r { q + 1 } else { q };
+
+        return start..(start + len);
+    }
+
+    pub fn get(&self, i: u64) -> u64 {
+        match self.order {
+            SearchOrder::Sequential => i,
+            SearchOrder::Strided => self.strided(i),
+            SearchOrder::Feistel => {
+                // cycle walk until we land back inside the space
+                let mut x = self.feistel(i);
+                while x >= self.total {
+                    x = self.feistel(x);
+                }
+                x
+            },
+        }
+    }
+
+    /** logical block t is column t of the real indices laid out `threads` wide
+     */
+    fn strided(&self, i: u64) -> u64 {
+        let q = self.total / self.threads;
+        let r = self.total % self.threads;
+        let tall = r * (q + 1);
+
+        let (column, row) = if i < tall {
+            (i / (q + 1), i % (q + 1))
+        } else {
+            (r + (i - tall) / q, (i - tall) % q)
+        };
+
+        return row * self.threads + column;
+    }
+
+    fn feistel(&self, x: u64) -> u64 {
+        let bits = 64 - self.total.saturating_sub(1).leading_zeros();
+        let half = bits.div_ceil(2).max(1);
+        let mask = (1u64 << half) - 1;
+
+        let mut left = x >> half;
+        let mut right = x & mask;
+
+        for round in 0..4u64 {
+            let f = mix(right ^ self.key ^ round.wrapping_mul(0x9e3779b97f4a7c15)) & mask;
+            (left, right) = (right, left ^ f);
+        }
+
+        return (left << half) | right;
+    }
+}
+
+/** splitmix64 finalizer
+ */
+fn mix(x: u64) -> u64 {
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_dispatch_exactly_once() {
+        for order in [SearchOrder::Sequential, SearchOrder::Strided, SearchOrder::Feistel] {
+            for total in [0u64, 1, 2, 5, 7, 100, 1023, 1024, 1025, 299997] {
+                for threads in [1u64, 2, 3, 8] {
+                    let permutation = Permutation::new(order, 42, total, threads);
+                    let mut seen = vec![false; total as usize];
+                    let mut covered = 0;
+
+                    for t in 0..threads {
+                        let range = permutation.thread_range(t);
+                        assert_eq!(range.start, covered);
+                        covered = range.end;
+
+                        for i in range {
+                            let x = permutation.get(i);
+                            assert!(x < total, "{:?} {} {} went to {}", order, total, threads, x);
+                            assert!(!seen[x as usize], "{:?} {} {} hit {} twice", order, total, threads, x);
+                            seen[x as usize] = true;
+                            if order == SearchOrder::Strided {
+                                assert_eq!(x % threads, t);
+                            }
+                        }
+                    }
+                    assert_eq!(covered, total);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn feistel_keys_differ() {
+        let a = Permutation::new(SearchOrder::Feistel, 42, 1000, 1);
+        let b = Permutation::new(SearchOrder::Feistel, 43, 1000, 1);
+
+        assert!((0..1000).any(|i| a.get(i) != b.get(i)));
+        assert!((0..1000).any(|i| a.get(i) != i));
+    }
+}
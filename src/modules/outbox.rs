@@ -0,0 +1,250 @@
+// finds get written down before we try to submit them so a dead server doesnt eat them
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json;
+
+use crate::modules::helpers;
+use crate::modules::nonce::NonceEncoding;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Find {
+    pub challenge: String,
+    pub nonce: u64,
+    pub encoding: NonceEncoding,
+    /** sha1 of challenge + nonce, hex
+     */
+    pub digest: String,
+    /** unix seconds
+     */
+    pub found_at: u64,
+}
+
+impl Find {
+    pub fn new(challenge: &str, nonce: u64, encoding: NonceEncoding, hashes: &[u32]) -> Find {
+        Find {
+            challenge: challenge.to_string(),
+            nonce,
+            encoding,
+            digest: hashes.iter().map(|w| format!("{:08x}", w)).collect(),
+            found_at: helpers::now(),
+        }
+    }
+}
+
+/** dropped finds kept around, the oldest go first
+ */
+const KEEP_DROPPED: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Dropped {
+    find: Find,
+    reason: String,
+    dropped_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Outbox {
+    #[serde(skip)]
+    path: String,
+    pending: Vec<Find>,
+    /** the last KEEP_DROPPED finds that never got paid and why, kept so theres something to point at
+     */
+    dropped: Vec<Dropped>,
+    /** pending finds whose last submit timed out or lost the connection, the server might have them already
+     */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    maybe_sent: Vec<(String, u64)>,
+    /** nonces the server paid for, per challenge, so a restart still wont send them again. only the current challenge's are kept
+     */
+    #[serde(default)]
+    accepted: HashMap<String, Vec<u64>>,
+    /** every (challenge, nonce) thats been through here, so the same find never goes out twice
+     */
+    #[serde(skip)]
+    seen: HashSet<(String, u64)>,
+}
+
+impl Outbox {
+    pub fn load(account: Option<&str>, thread_id: u32) -> Outbox {
+        return Outbox::open(helpers::thread_file("outbox", account, thread_id));
+    }
+
+    fn open(path: String) -> Outbox {
+        let mut outbox: Outbox = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                // dont start fresh over it, that would throw the pending finds away on the next save
+                panic!("couldnt read {}, fix or move it: {}", path, err);
+            }),
+            Err(_) => Outbox::default(),
+        };
+        outbox.path = path;
+        outbox.seen = outbox.pending.iter()
+            .chain(outbox.dropped.iter().map(|d| &d.find))
+            .map(|f| (f.challenge.clone(), f.nonce))
+            .chain(outbox.accepted.iter().flat_map(|(c, nonces)| nonces.iter().map(|n| (c.clone(), *n))))
+            .collect();
+
+        if !outbox.pending.is_empty() {
+            println!("{} finds waiting to be submitted", outbox.pending.len());
+        }
+
+        return outbox;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn pending(&self) -> Vec<Find> {
+        self.pending.clone()
+    }
+
+    /** journals the find, its on disk by the time this returns. false if we've had it before
+     */
+    pub fn push(&mut self, find: Find) -> bool {
+        if !self.seen.insert((find.challenge.clone(), find.nonce)) {
+            return false;
+        }
+
+        self.pending.push(find);
+        self.save();
+
+        return true;
+    }
+
+    /** the server took it
+     */
+    pub fn resolve(&mut self, find: &Find) {
+        self.forget(find);
+        self.accepted.entry(find.challenge.clone()).or_default().push(find.nonce);
+        self.save();
+    }
+
+    pub fn drop_find(&mut self, find: &Find, reason: &str) {
+        println!("dropping nonce {} for {}: {}", find.encoding.format(find.nonce), find.challenge, reason);
+
+        self.forget(find);
+        self.dropped.push(Dropped {
+            find: find.clone(),
+            reason: reason.to_string(),
+            dropped_at: helpers::now(),
+        });
+        if self.dropped.len() > KEEP_DROPPED {
+            self.dropped.drain(..self.dropped.len() - KEEP_DROPPED);
+        }
+        self.save();
+    }
+
+    /** the last try went wrong after it might have reached the server, so dont just send it again
+     */
+    pub fn maybe_sent(&mut self, find: &Find) {
+        let key = (find.challenge.clone(), find.nonce);
+        if !self.maybe_sent.contains(&key) {
+            self.maybe_sent.push(key);
+            self.save();
+        }
+    }
+
+    pub fn is_maybe_sent(&self, find: &Find) -> bool {
+        self.maybe_sent.iter().any(|(c, n)| *c == find.challenge && *n == find.nonce)
+    }
+
+    /** the server is on `hash` now, finds for older challenges cant come up again
+     */
+    pub fn moved_on(&mut self, hash: &str) {
+        let before = self.accepted.len();
+        self.accepted.retain(|c, _| c == hash);
+        self.seen.retain(|(c, _)| c == hash || self.pending.iter().any(|f| f.challenge == *c));
+
+        if self.accepted.len() != before {
+            self.save();
+        }
+    }
+
+    fn forget(&mut self, find: &Find) {
+        self.pending.retain(|f| f != find);
+        self.maybe_sent.retain(|(c, n)| *c != find.challenge || *n != find.nonce);
+    }
+
+    /** write then rename, a crash halfway through leaves the old journal instead of half of one
+     */
+    fn save(&self) {
+        let tmp = self.path.clone() + ".tmp";
+
+        let written = File::create(&tmp)
+            .map_err(|err| err.to_string())
+            .and_then(|mut file| {
+                serde_json::to_writer(&mut file, self).map_err(|err| err.to_string())?;
+                file.sync_all().map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&tmp, &self.path).map_err(|err| err.to_string()));
+
+        if let Err(err) = written {
+            println!("couldnt write {}: {}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn find(challenge: &str, nonce: u64) -> Find {
+        Find::new(challenge, nonce, NonceEncoding::Hex, &[0, 1, 2, 3, 4])
+    }
+
+    #[test]
+    fn same_find_goes_out_once_even_after_a_restart() {
+        let path = env::temp_dir().join(format!("outbox-test-{}.json", std::process::id())).to_string_lossy().to_string();
+        let mut outbox = Outbox::open(path.clone());
+
+        assert!(outbox.push(find("abc", 1)));
+        assert!(!outbox.push(find("abc", 1)));
+        assert!(outbox.push(find("abc", 2)));
+        outbox.resolve(&find("abc", 1));
+        outbox.maybe_sent(&find("abc", 2));
+        assert_eq!(outbox.pending(), vec![find("abc", 2)]);
+
+        let mut outbox = Outbox::open(path.clone());
+        assert!(!outbox.push(find("abc", 1)), "paid before the restart");
+        assert!(!outbox.push(find("abc", 2)), "still pending");
+        assert!(outbox.is_maybe_sent(&find("abc", 2)));
+        assert!(!outbox.is_maybe_sent(&find("abc", 1)));
+
+        // a new challenge doesnt need the old one's nonces anymore
+        outbox.drop_find(&find("abc", 2), "test");
+        outbox.moved_on("def");
+        assert!(outbox.accepted.is_empty() && outbox.maybe_sent.is_empty());
+        assert!(outbox.push(find("def", 1)));
+
+        let mut outbox = Outbox::open(path.clone());
+        fs::remove_file(&path).unwrap();
+        assert!(!outbox.push(find("def", 1)));
+        assert!(outbox.accepted.is_empty());
+    }
+
+    #[test]
+    fn only_the_last_drops_are_kept() {
+        let path = env::temp_dir().join(format!("outbox-drops-test-{}.json", std::process::id())).to_string_lossy().to_string();
+        let mut outbox = Outbox::open(path.clone());
+
+        for nonce in 0..KEEP_DROPPED as u64 + 10 {
+            outbox.push(find("abc", nonce));
+            outbox.drop_find(&find("abc", nonce), "test");
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(outbox.dropped.len(), KEEP_DROPPED);
+        assert_eq!(outbox.dropped[0].find.nonce, 10);
+        assert_eq!(text.lines().count(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+// remembers which dispatches we already burned through so a restart doesnt redo them
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::mem;
+use std::ops::Range;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json;
+
+use crate::modules::helpers;
+use crate::modules::nonce::NonceEncoding;
+use crate::modules::order::Permutation;
+
+/** how often the log is written while mining
+ */
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/**
+ * scanned logical dispatch indices from one starting nonce, relative to `nonce`
+ * logical dispatch i covers `encoding.stride()` nonces starting at nonce + permutation.get(i) * stride
+ */
+#[derive(Serialize, Deserialize, Debug)]
+struct Block {
+    encoding: NonceEncoding,
+    nonce: u64,
+    #[serde(default = "Permutation::sequential")]
+    permutation: Permutation,
+    scanned: Vec<Range<u64>>,
+}
+
+impl Block {
+    fn new(encoding: NonceEncoding, nonce: u64, permutation: Permutation) -> Block {
+        Block {
+            encoding,
+            nonce,
+            permutation,
+            scanned: Vec::new(),
+        }
+    }
+}
+
+/** everything mined for one challenge
+ */
+#[derive(Serialize, Deserialize, Debug)]
+struct Challenge {
+    /** how many times this hash ran out of nonces and moved on, so a restart doesnt go back to the start
+     */
+    #[serde(default)]
+    reseeds: u64,
+    /** the block being mined, flattened so logs from before there were several still load
+     */
+    #[serde(flatten)]
+    current: Block,
+    /** blocks it moved on from, kept in case it comes back to one
+     */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    earlier: Vec<Block>,
+}
+
+pub struct ScanLog {
+    path: String,
+    challenges: HashMap<String, Challenge>,
+    last_save: Instant,
+}
+
+impl ScanLog {
+    pub fn load(account: Option<&str>, thread_id: u32) -> ScanLog {
+        let path = helpers::thread_file("scanned", account, thread_id);
+        let challenges = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                println!("couldnt read {}, starting fresh: {}", path, err);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        ScanLog {
+            path,
+            challenges,
+            last_save: Instant::now(),
+        }
+    }
+
+    /** starts (or resumes) mining `hash` from `nonce`, returns the dispatches already scanned
+     */
+    pub fn begin(&mut self, hash: &str, encoding: NonceEncoding, nonce: u64, permutation: Permutation) -> Vec<Range<u64>> {
+        let challenge = self.challenges.entry(hash.to_string()).or_insert(Challenge {
+            reseeds: 0,
+            current: Block::new(encoding, nonce, permutation),
+            earlier: Vec::new(),
+        });
+
+        // a different starting point is a different set of nonces, the old one keeps its ranges
+        if challenge.current.encoding != encoding || challenge.current.nonce != nonce {
+            let block = match challenge.earlier.iter().position(|b| b.encoding == encoding && b.nonce == nonce) {
+                Some(i) => challenge.earlier.remove(i),
+                None => Block::new(encoding, nonce, permutation),
+            };
+            let old = mem::replace(&mut challenge.current, block);
+            if !old.scanned.is_empty() {
+                challenge.earlier.push(old);
+            }
+        }
+
+        // scanned in a different order, none of it lines up
+        let block = &mut challenge.current;
+        if !block.permutation.lines_up(&permutation) {
+            block.scanned.clear();
+        }
+        block.permutation = permutation;
+
+        if !block.scanned.is_empty() {
+            let done: u64 = block.scanned.iter().map(|r| r.end - r.start).sum();
+            println!("resuming {}, skipping {} scanned dispatches", hash, done);
+        }
+
+        return block.scanned.clone();
+    }
+
+    /** times `hash` ran out of nonces so far
+     */
+    pub fn reseeds(&self, hash: &str) -> u64 {
+        self.challenges.get(hash).map(|c| c.reseeds).unwrap_or(0)
+    }
+
+    /** `hash` ran out of nonces where it was, saved right away so a restart doesnt mine them again
+     */
+    pub fn reseed(&mut self, hash: &str) {
+        if let Some(challenge) = self.challenges.get_mut(hash) {
+            challenge.reseeds += 1;
+            self.save();
+        }
+    }
+
+    pub fn record(&mut self, hash: &str, range: Range<u64>) {
+        let challenge = match self.challenges.get_mut(hash) {
+            Some(c) => c,
+            None => return,
+        };
+
+        let block = &mut challenge.current;
+        block.scanned.push(range);
+        block.scanned.sort_by_key(|r| r.start);
+
+        // merge touching ranges so the file stays small
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(block.scanned.len());
+        for range in block.scanned.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        block.scanned = merged;
+
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    /** forgets a challenge once its solved or the server moved on
+     */
+    pub fn finish(&mut self, hash: &str) {
+        if self.challenges.remove(hash).is_some() {
+            self.save();
+        }
+    }
+
+    /** write then rename like the outbox, a crash halfway leaves the old log
+     */
+    pub fn save(&mut self) {
+        self.last_save = Instant::now();
+        let tmp = self.path.clone() + ".tmp";
+
+        let written = File::create(&tmp)
+            .map_err(|err| err.to_string())
+            .and_then(|mut file| {
+                serde_json::to_writer(&mut file, &self.challenges).map_err(|err| err.to_string())?;
+                file.sync_all().map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&tmp, &self.path).map_err(|err| err.to_string()));
+
+        if let Err(err) = written {
+            println!("couldnt write {}: {}", self.path, err);
+        }
+    }
+}
+
+/** parts of `range` not covered by `scanned`, which must be sorted and merged
+ */
+pub fn subtract(range: Range<u64>, scanned: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut out = Vec::new();
+    let mut start = range.start;
+
+    for s in scanned {
+        if s.end <= start {
+            continue;
+        }
+        if s.start >= range.end {
+            break;
+        }
+        if s.start > start {
+            out.push(start..s.start);
+        }
+        start = start.max(s.end);
+    }
+
+    if start < range.end {
+        out.push(start..range.end);
+    }
+
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::modules::order::SearchOrder;
+
+    #[test]
+    fn subtract_leaves_the_gaps() {
+        assert_eq!(subtract(0..100, &[]), vec![0..100]);
+        assert_eq!(subtract(0..100, &[10..20, 30..40]), vec![0..10, 20..30, 40..100]);
+        assert_eq!(subtract(50..100, &[10..60, 90..120]), vec![60..90]);
+        assert_eq!(subtract(50..100, &[0..60, 60..200]), vec![]);
+        assert_eq!(subtract(50..100, &[0..10, 200..300]), vec![50..100]);
+        assert_eq!(subtract(0..100, &[0..50, 50..100]), vec![]);
+    }
+
+    #[test]
+    fn logs_from_before_search_orders_still_load() {
+        let old = r#"{"abc":{"encoding":"hex","nonce":0,"scanned":[{"start":0,"end":10}]}}"#;
+        let mut log = ScanLog {
+            path: String::new(),
+            challenges: serde_json::from_str(old).unwrap(),
+            last_save: Instant::now(),
+        };
+
+        let sequential = Permutation::new(SearchOrder::Sequential, 7, 100, 3);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 0, sequential), vec![0..10]);
+
+        let shuffled = Permutation::new(SearchOrder::Feistel, 7, 100, 3);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 0, shuffled), vec![]);
+    }
+
+    #[test]
+    fn moving_on_keeps_both_blocks_and_survives_a_restart() {
+        let path = env::temp_dir().join(format!("scanned-test-{}.json", std::process::id())).to_string_lossy().to_string();
+        let permutation = Permutation::new(SearchOrder::Sequential, 7, 100, 3);
+        let mut log = ScanLog { path: path.clone(), challenges: HashMap::new(), last_save: Instant::now() };
+
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 0, permutation), vec![]);
+        log.record("abc", 0..100);
+        log.reseed("abc");
+        assert_eq!(log.reseeds("abc"), 1);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 1000, permutation), vec![]);
+        log.record("abc", 0..40);
+        log.save();
+
+        let mut log = ScanLog { path: path.clone(), challenges: serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap(), last_save: Instant::now() };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(log.reseeds("abc"), 1);
+        assert_eq!(log.reseeds("def"), 0);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 1000, permutation), vec![0..40]);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 0, permutation), vec![0..100]);
+        assert_eq!(log.begin("abc", NonceEncoding::Hex, 1000, permutation), vec![0..40]);
+    }
+}
@@ -0,0 +1,334 @@
+// one account on one threadid: its server, its hash, its finds and its numbers
+use std::process;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::jason::Account;
+use crate::jason::ConfigFile;
+use crate::modules::ledger::Ledger;
+use crate::modules::network::failover::Endpoints;
+use crate::modules::network::gabserver::GabServer;
+use crate::modules::network::ratelimit::Limiter;
+use crate::modules::network::retry::NetError;
+use crate::modules::network::submitter::SubmitReport;
+use crate::modules::network::submitter::Submitter;
+use crate::modules::network::watcher::Watcher;
+use crate::modules::network::PoolClient;
+use crate::modules::network::SubmitOutcome;
+use crate::modules::network::OUTAGE_POLL;
+use crate::modules::outbox::Find;
+use crate::modules::outbox::Outbox;
+use crate::modules::progress::ScanLog;
+
+/** how often to ask for a new hash after running out of nonces for the current one
+ */
+const EXHAUSTED_POLL: Duration = Duration::from_secs(30);
+
+/** how often to have another go at finds stuck in the outbox while mining
+ */
+const OUTBOX_RETRY: Duration = Duration::from_secs(60);
+
+/** numbers since startup
+ */
+pub struct Stats {
+    pub finds: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    /** submissions that never reached the server
+     */
+    pub failed: u64,
+    pub reward: u64,
+    /** what the server last said the account has
+     */
+    pub balance: Option<u32>,
+    pub latency: Duration,
+}
+
+pub struct Session {
+    pub username: String,
+    pub thread_id: u32,
+    pub weight: u32,
+    pool: Arc<dyn PoolClient + Send + Sync>,
+    submitter: Submitter,
+    watcher: Option<Watcher>,
+    challenge_poll: Duration,
+    pub scan_log: ScanLog,
+    outbox: Outbox,
+    /** None behind the work proxy, the proxy keeps the books for what it sends upstream
+     */
+    ledger: Option<Arc<Mutex<Ledger>>>,
+    pub stats: Stats,
+    /** None until the server first gets asked
+     */
+    hash: Option<String>,
+    /** one find out at a time, so the ones behind it dont go out for a challenge it just solved
+     */
+    in_flight: Option<Find>,
+    outbox_tried: Instant,
+}
+
+impl Session {
+    /** `tagged` puts the username in the file names, for when theres more than one account
+     */
+    pub fn new(endpoints: Arc<Endpoints>, limiter: Arc<Limiter>, ledger: Arc<Mutex<Ledger>>, config: &ConfigFile, account: &Account, thread_id: u32, tagged: bool) -> Session {
+        let pool = Arc::new(GabServer::new(endpoints, limiter, config, account, thread_id));
+        return Session::with_pool(pool, Some(ledger), config, &account.username, account.weight, thread_id, tagged);
+    }
+
+    /** for when the hashes come from somewhere other than the server, like the work proxy
+     */
+    pub fn with_pool(pool: Arc<dyn PoolClient + Send + Sync>, ledger: Option<Arc<Mutex<Ledger>>>, config: &ConfigFile, username: &str, weight: u32, thread_id: u32, tagged: bool) -> Session {
+        let tag = if tagged { Some(username) } else { None };
+
+        Session {
+            username: username.to_string(),
+            thread_id,
+            weight,
+            submitter: Submitter::new(pool.clone()),
+            pool,
+            watcher: None,
+            challenge_poll: Duration::from_millis(config.challenge_poll_ms),
+            scan_log: ScanLog::load(tag, thread_id),
+            outbox: Outbox::load(tag, thread_id),
+            ledger,
+            stats: Stats {
+                finds: 0,
+                accepted: 0,
+                rejected: 0,
+                failed: 0,
+                reward: 0,
+                balance: None,
+                latency: Duration::ZERO,
+            },
+            hash: None,
+            in_flight: None,
+            outbox_tried: Instant::now(),
+        }
+    }
+
+    /** the hash to mine, asks the server (and keeps asking through outages) the first time
+     */
+    pub fn hash(&mut self) -> String {
+        if let Some(hash) = &self.hash {
+            return hash.clone();
+        }
+
+        let hash = loop {
+            match self.pool.get_hash() {
+                Ok(hash) => break hash,
+                Err(NetError::BadAuth) => self.bad_auth(),
+                Err(err) => {
+                    println!("{}: couldnt get a hash, trying again in {}s: {}", self.name(), OUTAGE_POLL.as_secs(), err);
+                    thread::sleep(OUTAGE_POLL);
+                },
+            }
+        };
+
+        if self.challenge_poll > Duration::ZERO {
+            self.watcher = Some(Watcher::new(self.pool.clone(), self.challenge_poll, &hash));
+        }
+        self.set_hash(hash.clone());
+        self.next_submission();
+
+        return hash;
+    }
+
+    fn set_hash(&mut self, hash: String) {
+        self.outbox.moved_on(&hash);
+        if let Some(w) = &self.watcher {
+            w.set_current(&hash);
+        }
+        self.hash = Some(hash);
+    }
+
+    /** blocks until the server hands out something other than `hash`, for when theres no nonces left to try at all
+     */
+    pub fn wait_for_new_hash(&mut self, hash: &str) {
+        loop {
+            thread::sleep(EXHAUSTED_POLL);
+
+            match self.pool.get_hash() {
+                Ok(new_hash) if new_hash != hash => {
+                    self.set_hash(new_hash);
+                    return;
+                },
+                Ok(_) => {},
+                Err(NetError::BadAuth) => self.bad_auth(),
+                Err(err) => println!("{}: couldnt get a hash: {}", self.name(), err),
+            }
+        }
+    }
+
+    /**
+     * picks up a hash change from the watcher and whatever the submitter finished
+     * true if the hash to mine changed
+     */
+    pub fn poll(&mut self) -> bool {
+        let before = self.hash.clone();
+
+        if let Some(new_hash) = self.watcher.as_ref().and_then(|w| w.try_changed()) {
+            if Some(&new_hash) != before.as_ref() {
+                println!("{}: server moved from {} to {}, switching", self.name(), before.as_deref().unwrap_or("nothing"), new_hash);
+                self.set_hash(new_hash);
+            }
+        }
+
+        if let Some(report) = self.submitter.try_report() {
+            let offline = matches!(report.outcome, Some(Err(_)));
+            self.in_flight = None;
+            self.outbox_tried = Instant::now();
+
+            if let Some(new_hash) = self.handle_report(report) {
+                self.set_hash(new_hash);
+            }
+            // no point lining up the next one while the server is unreachable
+            if !offline {
+                self.next_submission();
+            }
+        }
+
+        return self.hash != before;
+    }
+
+    /** journals a verified find and sends it off unless another one is still out
+     */
+    pub fn found(&mut self, find: Find, found_at: Instant) {
+        if !self.outbox.push(find.clone()) {
+            println!("{}: already had nonce {} for {}, not sending it twice", self.name(), find.encoding.format(find.nonce), find.challenge);
+            return;
+        }
+        self.stats.finds += 1;
+
+        if self.in_flight.is_none() {
+            self.submitter.submit(find.clone(), found_at, false);
+            self.in_flight = Some(find);
+        }
+    }
+
+    /** another go at the outbox if its been a while
+     */
+    pub fn retry_outbox(&mut self) {
+        if self.in_flight.is_none() && !self.outbox.is_empty() && self.outbox_tried.elapsed() >= OUTBOX_RETRY {
+            self.outbox_tried = Instant::now();
+            self.next_submission();
+        }
+    }
+
+    /** sends the oldest journaled find for the current hash, dropping the ones for a challenge the server moved on from
+     */
+    fn next_submission(&mut self) {
+        let hash = match &self.hash {
+            Some(h) => h.clone(),
+            None => return,
+        };
+
+        for find in self.outbox.pending() {
+            if find.challenge != hash {
+                self.outbox.drop_find(&find, "challenge moved on before it got through");
+                continue;
+            }
+
+            // latency for these counts from the resend, the gpu side of it is long gone
+            self.submitter.submit(find.clone(), Instant::now(), self.outbox.is_maybe_sent(&find));
+            self.in_flight = Some(find);
+            return;
+        }
+    }
+
+    /** returns the hash to mine next if the server gave us one
+     */
+    fn handle_report(&mut self, report: SubmitReport) -> Option<String> {
+        let find = report.find;
+        let nonce = find.encoding.format(find.nonce);
+        self.stats.latency += report.latency;
+
+        let outcome = match report.outcome {
+            Some(o) => o,
+            None => {
+                // either it got through last time or the server moved on by itself, sending it again cant get paid
+                self.outbox.drop_find(&find, "server moved on since a submit that might have got through");
+                return report.new_hash.and_then(|h| h.ok());
+            },
+        };
+
+        match outcome {
+            Ok(SubmitOutcome::Accepted { reward, balance, newhash }) => {
+                self.outbox.resolve(&find);
+                self.stats.accepted += 1;
+                self.stats.reward += reward as u64;
+                self.stats.balance = Some(balance);
+                if let Some(ledger) = &self.ledger {
+                    ledger.lock().unwrap().record(&self.username, self.thread_id, reward, balance);
+                }
+                println!("{}: Nonce got: {}, Hash: {}, Reward: {}, Balance: {}", self.name(), nonce, find.digest, reward, balance);
+                println!("submitted in {}ms, {}ms after the gpu found it", report.round_trip.as_millis(), report.latency.as_millis());
+                self.print_stats();
+                Some(newhash)
+            },
+            Ok(SubmitOutcome::BadNonce) => {
+                // the cpu agreed with the gpu so the hash is still worth mining
+                self.stats.rejected += 1;
+                self.outbox.drop_find(&find, "server says the nonce is wrong");
+                println!("{}: bad nonce: {}, hash: {}, source hash {}", self.name(), nonce, find.digest, find.challenge);
+                None
+            },
+            Ok(outcome @ SubmitOutcome::StaleHash) | Ok(outcome @ SubmitOutcome::Unknown(_)) => {
+                let reason = match outcome {
+                    SubmitOutcome::Unknown(status) => format!("server said {}", status),
+                    _ => String::from("challenge expired"),
+                };
+                self.stats.rejected += 1;
+                self.outbox.drop_find(&find, &reason);
+                println!("{}: {} for nonce {}, switching to the new hash", self.name(), reason, nonce);
+
+                // the submitter already asked for it
+                match report.new_hash {
+                    Some(Ok(h)) => Some(h),
+                    Some(Err(NetError::BadAuth)) => self.bad_auth(),
+                    Some(Err(err)) => {
+                        println!("{}: couldnt get a new hash, staying on this one: {}", self.name(), err);
+                        None
+                    },
+                    None => None,
+                }
+            },
+            Ok(SubmitOutcome::BadAuth) | Err(NetError::BadAuth) => self.bad_auth(),
+            Err(err) => {
+                self.stats.failed += 1;
+                if !err.never_arrived() {
+                    self.outbox.maybe_sent(&find);
+                }
+                println!("{}: couldnt submit nonce {}, its in the outbox: {}", self.name(), nonce, err);
+                None
+            },
+        }
+    }
+
+    pub fn print_stats(&self) {
+        let s = &self.stats;
+        let answered = (s.accepted + s.rejected + s.failed).max(1) as u32;
+        let balance = s.balance.map(|b| b.to_string()).unwrap_or_else(|| String::from("?"));
+
+        println!(
+            "{}: {} found, {} accepted, {} rejected, {} failed, {} reward, balance {}, {}ms average latency",
+            self.name(), s.finds, s.accepted, s.rejected, s.failed, s.reward, balance, (s.latency / answered).as_millis()
+        );
+        if let Some(health) = self.pool.health() {
+            println!("servers: {}", health);
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.username, self.thread_id)
+    }
+
+    /** asking again with the same password wont help, finds stay in the outbox for next time
+     */
+    fn bad_auth(&self) -> ! {
+        println!("server doesnt take the password for {}, fix config.json", self.username);
+        process::exit(1);
+    }
+}